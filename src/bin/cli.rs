@@ -4,12 +4,15 @@ extern crate log;
 use std::{collections::HashSet, str::FromStr, path::PathBuf};
 
 use anyhow::Result;
-use telegram_forcast56::onu::{self, PortForwardingHost, PortForwardingParam, PortForwardingProtocol};
+use telegram_forcast56::onu::{self, PortForwardingParam, PortForwardingProtocol, PortForwardingSelector, PortForwardingTarget};
 use clap::{Parser, Subcommand, ValueEnum};
 
+/// milliseconds `port-forwarding check` and `new --check` wait for every port
+const CHECK_TIMEOUT_MS: u64 = 1000;
+
 #[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
 enum InfoTarget {
-  Lan, Wan, #[clap(name = "upnp")] UPnP, Wanc, Wan6
+  Lan, Wan, #[clap(name = "upnp")] UPnP, VirtualServer, Wanc, Wan6, Wlan, WlanClients
 }
 
 impl std::fmt::Display for InfoTarget {
@@ -26,12 +29,15 @@ impl std::fmt::Display for InfoTarget {
 struct PortRange(u32, u32);
 
 impl FromStr for PortRange {
-    type Err = std::num::ParseIntError;
+    type Err = String;
 
     fn from_str(s: &str) -> std::prelude::v1::Result<Self, Self::Err> {
       let mut iter = s.splitn(2, ':');
-      let min = iter.next().unwrap().parse()?;
-      let max = iter.next().map(str::parse::<u32>).transpose()?.unwrap_or(min);
+      let min = iter.next().unwrap().parse().map_err(|e| format!("{}: {}", s, e))?;
+      let max = iter.next().map(str::parse::<u32>).transpose().map_err(|e| format!("{}: {}", s, e))?.unwrap_or(min);
+      if min > max {
+        return Err(format!("{}: the first port is greater than the last", s));
+      }
       Ok(Self(min, max))
     }
 }
@@ -46,6 +52,59 @@ impl std::fmt::Display for PortRange {
   }
 }
 
+/// External port of a new rule, possible values: 3389, 8000:8999, auto, auto:20000:29999
+#[derive(Debug, Clone, Copy)]
+enum ExternalPort {
+  Fixed(PortRange),
+  /// the first free ports inside the range
+  Auto(PortRange),
+}
+
+impl ExternalPort {
+  const AUTO_RANGE: PortRange = PortRange(20000, 29999);
+}
+
+impl FromStr for ExternalPort {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::prelude::v1::Result<Self, Self::Err> {
+    match s.strip_prefix("auto") {
+      Some("") => Ok(Self::Auto(Self::AUTO_RANGE)),
+      Some(range) => Ok(Self::Auto(range.trim_start_matches(':').parse()?)),
+      None => Ok(Self::Fixed(s.parse()?)),
+    }
+  }
+}
+
+/// Remote host range, possible values: 203.0.113.7, 203.0.113.0-203.0.113.255
+#[derive(Debug, Clone)]
+struct HostRange(String, String);
+
+impl FromStr for HostRange {
+  type Err = std::convert::Infallible;
+
+  fn from_str(s: &str) -> std::prelude::v1::Result<Self, Self::Err> {
+    match s.split_once('-') {
+      Some((min, max)) => Ok(Self(min.trim().to_string(), max.trim().to_string())),
+      None => Ok(Self(s.to_string(), s.to_string())),
+    }
+  }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum RuleFormat {
+  Csv, Json,
+}
+
+impl From<RuleFormat> for onu::backup::RuleFormat {
+  fn from(value: RuleFormat) -> Self {
+    match value {
+      RuleFormat::Csv => Self::Csv,
+      RuleFormat::Json => Self::Json,
+    }
+  }
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
   #[command(arg_required_else_help = true)]
@@ -56,28 +115,268 @@ enum Commands {
   PortForwarding {
     #[command(subcommand)]
     action: PortForwardingAction
-  }
+  },
+  /// send all unsolicited inbound traffic of a WAN to one host
+  #[command(arg_required_else_help = true)]
+  Dmz {
+    #[command(subcommand)]
+    action: DmzAction
+  },
+  /// turn the UPnP service on or off, see `info upnp` for the mappings
+  #[command(arg_required_else_help = true)]
+  Upnp {
+    #[command(subcommand)]
+    action: UpnpAction
+  },
+  /// static DHCP leases, so a host keeps its address
+  #[command(arg_required_else_help = true)]
+  Dhcp {
+    #[command(subcommand)]
+    action: DhcpAction
+  },
+  #[command(arg_required_else_help = true)]
+  Lan {
+    #[command(subcommand)]
+    action: LanAction
+  },
+  #[command(arg_required_else_help = true)]
+  Wlan {
+    #[command(subcommand)]
+    action: WlanAction
+  },
+  /// a secondary SSID for visitors with a fresh passphrase
+  #[command(arg_required_else_help = true)]
+  GuestWifi {
+    #[arg(long, help = "where to remember when to turn the guest SSIDs off, ~/.onu-guest-wifi.json by default")]
+    state: Option<PathBuf>,
+    #[command(subcommand)]
+    action: GuestWifiAction
+  },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum GuestWifiAction {
+  /// turn it on, show the QR code, and keep running to turn it off when the time runs out,
+  /// `guest-wifi gc` turns it off in case this is stopped before
+  #[command(arg_required_else_help = true)]
+  On {
+    #[arg(long = "for", value_name = "DURATION", help = "possible values: 30m, 4h")]
+    ttl: humantime::Duration,
+    #[arg(long, help = "index or name of the SSID in `info wlan`, the first secondary SSID by default")]
+    ssid: Option<String>,
+  },
+  Off {
+    #[arg(long, help = "index or name of the SSID in `info wlan`, the first secondary SSID by default")]
+    ssid: Option<String>,
+  },
+  /// turn off the guest SSIDs whose time has run out, e.g. from cron
+  Gc,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum WlanAction {
+  /// change the name or passphrase of an SSID, connected clients are dropped
+  #[command(arg_required_else_help = true)]
+  Set {
+    #[arg(help = "index or name of the SSID in `info wlan`")]
+    ssid: String,
+    #[arg(long, help = "rename the SSID")]
+    name: Option<String>,
+    #[arg(long)]
+    passphrase: Option<String>,
+  },
+  #[command(arg_required_else_help = true)]
+  Channel {
+    #[arg(help = "index or band of the radio, possible values: 0, 2.4, 5GHz")]
+    radio: String,
+    #[arg(help = "possible values: 6, 149, auto")]
+    channel: String,
+  },
+  /// scan for other networks and rank the channels of a radio, the least crowded first
+  #[command(arg_required_else_help = true)]
+  Survey {
+    #[arg(help = "index or band of the radio, possible values: 0, 2.4, 5GHz")]
+    radio: String,
+    #[arg(long, help = "move the radio to the least crowded channel")]
+    apply: bool,
+  },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum LanAction {
+  /// the LAN address of the router and its DHCP server
+  #[command(arg_required_else_help = true)]
+  Config {
+    #[command(subcommand)]
+    action: LanConfigAction
+  },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum LanConfigAction {
+  Show,
+  /// change only the given fields
+  #[command(arg_required_else_help = true)]
+  Set {
+    #[arg(long, help = "the address of the router, the web UI moves along")]
+    lan_ip: Option<String>,
+    #[arg(long)]
+    subnet_mask: Option<String>,
+    #[arg(long, value_name = "BOOL")]
+    server_enable: Option<bool>,
+    #[arg(long, value_name = "ADDR", help = "addresses handed out, possible values: 192.168.1.100-192.168.1.199")]
+    pool: Option<HostRange>,
+    #[arg(long, value_name = "DURATION", help = "possible values: 1h, 1day")]
+    lease_time: Option<humantime::Duration>,
+    #[arg(long)]
+    gateway: Option<String>,
+    #[arg(long, num_args = 1..=3, value_delimiter = ',', help = "dns servers handed out, up to 3, possible values: 192.168.1.2,1.1.1.1")]
+    dns: Option<Vec<String>>,
+  },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum DhcpAction {
+  List,
+  /// always lease the address to the host, its current lease by default
+  #[command(arg_required_else_help = true)]
+  Reserve {
+    #[arg(help = "possible values: aa:bb:cc:dd:ee:01, 192.168.1.4, a hostname in `info lan`, self")]
+    target: PortForwardingTarget,
+    ip: Option<String>,
+  },
+  #[command(arg_required_else_help = true)]
+  Delete {
+    #[arg(help = "the mac, ip or name of the binding")]
+    mac_or_ip: String,
+  },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum UpnpAction {
+  Enable,
+  /// the router drops every mapping of UPnP clients
+  Disable,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum DmzAction {
+  Show,
+  #[command(arg_required_else_help = true)]
+  Set {
+    #[arg(long)]
+    wanc: Option<String>,
+    #[arg(long, help = "use the mac address of the target, so it survives DHCP renumbering")]
+    mac: bool,
+    #[arg(help = "possible values: 192.168.1.4, aa:bb:cc:dd:ee:01, a hostname in `info lan`, self")]
+    addr: PortForwardingTarget,
+  },
+  Clear {
+    #[arg(long)]
+    wanc: Option<String>,
+  },
 }
 
 #[derive(Debug, Clone, Subcommand)]
 enum PortForwardingAction {
   #[command(arg_required_else_help = true)]
   New {
-    #[arg(long, name = "PORT")]
-    external_port: Option<PortRange>,
+    #[arg(long, name = "PORT", help = "external port, possible values: 3389, 8000:8999, auto, auto:20000:29999")]
+    external_port: Option<ExternalPort>,
     #[arg(long)]
     wanc: Option<String>,
     #[arg(long)]
     protocol: Option<PortForwardingProtocol>,
+    #[arg(long, value_name = "ADDR", help = "only allow these remote hosts, possible values: 203.0.113.7, 203.0.113.0-203.0.113.255")]
+    remote_host: Option<HostRange>,
+    #[arg(long)]
+    description: Option<String>,
+    #[arg(long, value_name = "DURATION", help = "delete the rule by `gc` after this long, possible values: 30m, 2h")]
+    ttl: Option<humantime::Duration>,
+    #[arg(long, help = "forward to the mac address of the target, so it survives DHCP renumbering")]
+    mac: bool,
+    #[arg(long, help = "refuse to create the rule if nothing answers on the local port")]
+    check: bool,
+    #[arg(long, default_value_t = CHECK_TIMEOUT_MS, requires = "check", help = "milliseconds to wait for the port with --check")]
+    timeout: u64,
     name: String,
-    #[arg(help = "local address, possible values: 192.168.1.4")]
-    addr: PortForwardingHost,
+    #[arg(help = "local address, possible values: 192.168.1.4, aa:bb:cc:dd:ee:01, a hostname in `info lan`, self")]
+    addr: PortForwardingTarget,
     #[arg(help = "local port, possible values: 3389, 8000:8999")]
     port: PortRange,
   },
+  /// change only the given fields of an existing rule
+  #[command(arg_required_else_help = true)]
+  Edit {
+    name_or_index: PortForwardingSelector,
+    #[arg(long, help = "rename the rule")]
+    name: Option<String>,
+    #[arg(long, value_name = "PORT")]
+    external_port: Option<PortRange>,
+    #[arg(long, value_name = "PORT", help = "local port, possible values: 3389, 8000:8999")]
+    port: Option<PortRange>,
+    #[arg(long, help = "local address, possible values: 192.168.1.4, aa:bb:cc:dd:ee:01, a hostname in `info lan`, self")]
+    addr: Option<PortForwardingTarget>,
+    #[arg(long, requires = "addr", help = "forward to the mac address of --addr")]
+    mac: bool,
+    #[arg(long)]
+    protocol: Option<PortForwardingProtocol>,
+    #[arg(long)]
+    wanc: Option<String>,
+    #[arg(long)]
+    description: Option<String>,
+  },
+  /// turn a rule on without changing its settings
+  #[command(arg_required_else_help = true)]
+  Enable {
+    name_or_index: PortForwardingSelector,
+  },
+  /// turn a rule off, it could be enabled again later
+  #[command(arg_required_else_help = true)]
+  Disable {
+    name_or_index: PortForwardingSelector,
+  },
   #[command(arg_required_else_help = true)]
   Delete {
-    name_or_index: String,
+    name_or_index: PortForwardingSelector,
+  },
+  /// delete rules created with --ttl that have expired
+  Gc,
+  /// connect to the local targets from this machine and report the dead ones
+  Check {
+    name_or_index: Option<PortForwardingSelector>,
+    #[arg(long, default_value_t = CHECK_TIMEOUT_MS, help = "milliseconds to wait for every port")]
+    timeout: u64,
+  },
+  /// dump the rules (except those of UPnP clients) to stdout or a file
+  Export {
+    #[arg(long, help = "defaults to the extension of --output, or json")]
+    format: Option<RuleFormat>,
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+  },
+  /// load rules from an exported file, rows are submitted one by one
+  #[command(arg_required_else_help = true)]
+  Import {
+    file: PathBuf,
+    #[arg(long, help = "defaults to the extension of the file")]
+    format: Option<RuleFormat>,
+    #[arg(long, conflicts_with = "replace", help = "add new rules and overwrite the ones with the same name, the default")]
+    merge: bool,
+    #[arg(long, help = "also delete the rules not in the file")]
+    replace: bool,
+  },
+  /// show what `apply` would change to reach the desired state
+  #[command(arg_required_else_help = true)]
+  Plan {
+    #[arg(help = "desired state in .toml, .yaml or .json")]
+    file: PathBuf,
+  },
+  /// add, change and remove rules tagged `[managed]` to reach the desired state
+  #[command(arg_required_else_help = true)]
+  Apply {
+    #[arg(help = "desired state in .toml, .yaml or .json")]
+    file: PathBuf,
   },
 }
 
@@ -87,18 +386,38 @@ struct Cli {
   base_url: String,
   #[arg(long)]
   cache_path: Option<PathBuf>,
+  #[arg(long, default_value_t = 0, help = "seconds to wait for another admin session (e.g. the web UI) to end")]
+  wait_session: u64,
+  #[arg(long, help = "submit port forwarding rules without checking overlaps, reserved ports and addresses")]
+  no_validate: bool,
   #[command(subcommand)]
   command: Commands,
 }
 
-async fn ctx(base_url: &str) -> Result<onu::Context> {
+fn credentials() -> (String, String) {
   let username = std::env::var("router_username").unwrap();
   let password = std::env::var("router_password").unwrap();
+  (username, password)
+}
+
+async fn ctx(base_url: &str, wait_session: u64) -> Result<onu::Context> {
+  let (username, password) = credentials();
   let mut ctx = onu::Context::new(base_url);
-  ctx.login(&username, &password).await?;
+  let wait = std::time::Duration::from_secs(wait_session);
+  ctx.login_wait(&username, &password, wait, std::time::Duration::from_secs(5)).await?;
   Ok(ctx)
 }
 
+/// `wanc` or the first WAN connection of the router
+async fn wanc_or_default(ctx: &mut onu::Context, wanc: Option<String>) -> Result<String> {
+  if let Some(wanc) = wanc {
+    return Ok(wanc);
+  }
+  let info = ctx.wanc_info().await?;
+  let first = info.first().ok_or_else(|| anyhow::anyhow!("the router has no WAN connection, pass --wanc"))?;
+  Ok(first.view_name.clone())
+}
+
 fn json_to_csv(json: &[serde_json::Value]) -> Result<Vec<csv::StringRecord>> {
   let mut headers = Vec::new();
   let mut header_set = HashSet::new();
@@ -126,7 +445,7 @@ fn json_to_csv(json: &[serde_json::Value]) -> Result<Vec<csv::StringRecord>> {
 fn csv_to_table(records: &[csv::StringRecord]) -> tabled::Table {
   let mut builder = tabled::builder::Builder::new();
   // println!("{:?}", records[0]);
-  for i in records.get(0).unwrap() {
+  for i in records.first().unwrap() {
     builder.push_record(&[i.to_string()]);
   }
   records.iter().skip(1).for_each(|i| builder.push_column(i));
@@ -139,10 +458,22 @@ async fn main() -> Result<()> {
   flexi_logger::Logger::try_with_env_or_str("info").unwrap().start().ok();
   let args = Cli::parse();
   info!("{:?}", args);
-  match args.command {
+  let mut ctx = ctx(&args.base_url, args.wait_session).await?;
+  ctx.cache_path = args.cache_path;
+  if args.no_validate {
+    ctx.validator = None;
+  }
+  let result = run(&mut ctx, args.command).await;
+  // the router allows only one admin session, release it for the web UI
+  if let Err(e) = ctx.logout().await {
+    warn!("logout failed: {}", e);
+  }
+  result
+}
+
+async fn run(ctx: &mut onu::Context, command: Commands) -> Result<()> {
+  match command {
     Commands::Info { target } => {
-      let mut ctx = ctx(&args.base_url).await?;
-      ctx.cache_path = args.cache_path;
       match target {
         InfoTarget::Lan => {
           let info = ctx.lan_info().await?;
@@ -154,6 +485,11 @@ async fn main() -> Result<()> {
           println!("{}", csv_to_table(&json_to_csv(&info_json)?));
         }
         InfoTarget::UPnP => {
+          let info = ctx.upnp_status().await?;
+          println!("UPnP: {}", if info.enable { "enabled" } else { "disabled" });
+          println!("{}", tabled::Table::new(&info.mappings));
+        }
+        InfoTarget::VirtualServer => {
           let info = ctx.port_forwarding_list().await?;
           let view = info.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
           println!("{}", tabled::Table::new(&view));
@@ -162,6 +498,16 @@ async fn main() -> Result<()> {
           let info = ctx.wanc_info().await?;
           println!("{}", tabled::Table::new(&info));
         }
+        InfoTarget::Wlan => {
+          let radios = ctx.wlan_radios().await?;
+          println!("{}", tabled::Table::new(&radios));
+          let ssids = ctx.wlan_ssids().await?;
+          println!("{}", tabled::Table::new(&ssids));
+        }
+        InfoTarget::WlanClients => {
+          let clients = ctx.wlan_clients().await?;
+          println!("{}", tabled::Table::new(&clients));
+        }
         InfoTarget::Wan6 => {
           let info = ctx.wan6_info().await?;
           let info_json = info.iter().map(serde_json::to_value).collect::<Result<Vec<_>,_>>()?;
@@ -171,29 +517,283 @@ async fn main() -> Result<()> {
     },
     Commands::PortForwarding { action } => {
       match action {
-        PortForwardingAction::New { external_port,wanc,name,addr,port,protocol } => {
-          let external_port = external_port.unwrap_or(port);
+        PortForwardingAction::New { external_port,wanc,name,addr,port,protocol,remote_host,description,ttl,mac,check,timeout } => {
           let protocol = protocol.unwrap_or(PortForwardingProtocol::Both);
           // let name = name.unwrap_or_else(|| format!("__port_{}", port.0));
-          let mut ctx = ctx(&args.base_url).await?;
-          let wanc = match wanc {
-            Some(wanc) => wanc,
-            None => {
-              let info = ctx.wanc_info().await?;
-              info[0].view_name.clone()
+          let wanc = wanc_or_default(ctx, wanc).await?;
+          let external_port = match external_port.unwrap_or(ExternalPort::Fixed(port)) {
+            ExternalPort::Fixed(external_port) => external_port,
+            ExternalPort::Auto(range) => {
+              let (min, max) = ctx.port_forwarding_allocate(&protocol, &wanc, port.1 - port.0 + 1, (range.0, range.1)).await?;
+              PortRange(min, max)
             }
           };
-          ctx.port_forwarding(
-            onu::PortForwardingAction::New, name.as_str(), protocol, &wanc, addr,
-            onu::PortForwardingPort::Multiple { remote: (external_port.0, external_port.1), local: (port.0, port.1) }).await?;
+          let addr = ctx.resolve_target(&addr, mac).await?;
+          let mut param = PortForwardingParam::new(&name, &wanc, addr,
+            onu::PortForwardingPort::Multiple { remote: (external_port.0, external_port.1), local: (port.0, port.1) })
+            .with_protocol(protocol);
+          if let Some(HostRange(min, max)) = remote_host {
+            param = param.with_remote_host(&min, &max);
+          }
+          if let Some(description) = description {
+            param = param.with_description(&description);
+          }
+          if let Some(ttl) = ttl {
+            param = param.with_expiry(std::time::SystemTime::now() + *ttl);
+          }
+          if check {
+            let lan = if param.enable_local_mac { ctx.lan_info().await? } else { Vec::new() };
+            let result = onu::probe::check_rule(&param, &lan, std::time::Duration::from_millis(timeout)).await;
+            if result.is_dead() {
+              print_checks(&[result]);
+              anyhow::bail!("nothing answers on {}, the rule is not created", param.fingerprint());
+            }
+          }
+          let fingerprint = param.fingerprint();
+          let list = ctx.port_forwarding(onu::PortForwardingAction::New, param).await?;
+          // one json line for scripts, e.g. to tell the chosen external port
+          if let Some(created) = list.into_iter().find(|p| p.fingerprint() == fingerprint) {
+            println!("{}", serde_json::to_string(&PortForwardingParamView::from(created))?);
+          }
+        },
+        PortForwardingAction::Edit { name_or_index, name, external_port, port, addr, mac, protocol, wanc, description } => {
+          let lan = match addr {
+            Some(addr) => Some(ctx.resolve_target(&addr, mac).await?),
+            None => None,
+          };
+          let update = onu::PortForwardingUpdate {
+            name,
+            protocol,
+            wan_interface: wanc,
+            remote_port: external_port.map(|p| (p.0, p.1)),
+            local_port: port.map(|p| (p.0, p.1)),
+            lan,
+            description,
+            ..Default::default()
+          };
+          let list = ctx.port_forwarding_edit(&name_or_index, &update).await?;
+          let view = list.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
+          println!("{}", tabled::Table::new(&view));
+        },
+        PortForwardingAction::Enable { ref name_or_index } | PortForwardingAction::Disable { ref name_or_index } => {
+          let enable = matches!(action, PortForwardingAction::Enable { .. });
+          let list = ctx.port_forwarding_set_enable(name_or_index, enable).await?;
+          let view = list.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
+          println!("{}", tabled::Table::new(&view));
         },
         PortForwardingAction::Delete { name_or_index } => {
-          let action = match name_or_index.parse::<u32>().ok() {
-            Some(index) => onu::PortForwardingAction::Delete(index),
-            None => onu::PortForwardingAction::DeleteByName(name_or_index),
+          let list = ctx.port_forwarding_remove(&name_or_index).await?;
+          let view = list.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
+          println!("{}", tabled::Table::new(&view));
+        },
+        PortForwardingAction::Gc => {
+          let report = ctx.port_forwarding_gc(std::time::SystemTime::now()).await?;
+          for (title, list) in [("deleted", report.removed), ("not expired yet", report.pending), ("created by UPnP clients", report.upnp)] {
+            let view = list.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
+            println!("{}:\n{}", title, tabled::Table::new(&view));
+          }
+        },
+        PortForwardingAction::Check { name_or_index, timeout } => {
+          let checks = ctx.port_forwarding_check(name_or_index.as_ref(), std::time::Duration::from_millis(timeout)).await?;
+          print_checks(&checks);
+          let dead = checks.iter().filter(|c| c.is_dead()).count();
+          if dead > 0 {
+            anyhow::bail!("{} of {} rules point at dead targets", dead, checks.len());
+          }
+        },
+        PortForwardingAction::Export { format, output } => {
+          let format = match (format, &output) {
+            (Some(format), _) => format.into(),
+            (None, Some(output)) => onu::backup::RuleFormat::from_path(output),
+            (None, None) => onu::backup::RuleFormat::Json,
           };
-          let mut ctx = ctx(&args.base_url).await?;
-          ctx.port_forwarding_delete(action).await?;
+          let content = onu::backup::export_rules(&ctx.port_forwarding_list().await?, format)?;
+          match output {
+            Some(output) => std::fs::write(output, content)?,
+            None => print!("{}", content),
+          }
+        },
+        PortForwardingAction::Import { file, format, merge: _, replace } => {
+          let format = format.map(Into::into).unwrap_or_else(|| onu::backup::RuleFormat::from_path(&file));
+          let rows = onu::backup::load_rules(&file, format)?;
+          let mode = if replace { onu::backup::ImportMode::Replace } else { onu::backup::ImportMode::Merge };
+          let report = ctx.port_forwarding_import(rows, mode).await?;
+          for param in &report.removed {
+            println!("removed: {}", param.fingerprint());
+          }
+          let mut builder = tabled::builder::Builder::new();
+          builder.push_record(["row", "name", "result"]);
+          for (row, name, outcome) in &report.rows {
+            builder.push_record([row.to_string(), name.clone(), outcome.to_string()]);
+          }
+          println!("{}", builder.build());
+          if report.failed() > 0 {
+            anyhow::bail!("{} of {} rows failed", report.failed(), report.rows.len());
+          }
+        },
+        PortForwardingAction::Plan { file } => {
+          let desired = onu::sync::DesiredState::load(file)?;
+          let plan = ctx.port_forwarding_plan(&desired).await?;
+          if plan.is_empty() {
+            println!("up to date");
+          } else {
+            print!("{}", plan);
+          }
+        },
+        PortForwardingAction::Apply { file } => {
+          let desired = onu::sync::DesiredState::load(file)?;
+          let plan = ctx.port_forwarding_plan(&desired).await?;
+          if plan.is_empty() {
+            println!("up to date");
+            return Ok(())
+          }
+          print!("{}", plan);
+          let list = ctx.port_forwarding_sync(&plan).await?;
+          let view = list.into_iter().map(PortForwardingParamView::from).collect::<Vec<_>>();
+          println!("{}", tabled::Table::new(&view));
+        },
+      }
+    }
+    Commands::Dmz { action } => {
+      let list = match action {
+        DmzAction::Show => ctx.dmz_get().await?,
+        DmzAction::Set { wanc, mac, addr } => {
+          let wanc = wanc_or_default(ctx, wanc).await?;
+          let addr = ctx.resolve_target(&addr, mac).await?;
+          ctx.dmz_set(&wanc, Some(addr)).await?
+        },
+        DmzAction::Clear { wanc } => {
+          let wanc = wanc_or_default(ctx, wanc).await?;
+          ctx.dmz_set(&wanc, None).await?
+        },
+      };
+      println!("{}", tabled::Table::new(&list));
+    }
+    Commands::Upnp { action } => {
+      let status = ctx.upnp_set_enable(matches!(action, UpnpAction::Enable)).await?;
+      println!("UPnP: {}", if status.enable { "enabled" } else { "disabled" });
+      println!("{}", tabled::Table::new(&status.mappings));
+    }
+    Commands::Dhcp { action } => {
+      let list = match action {
+        DhcpAction::List => ctx.dhcp_bindings().await?,
+        DhcpAction::Reserve { target, ip } => ctx.dhcp_reserve(&target, ip.as_deref()).await?,
+        DhcpAction::Delete { mac_or_ip } => ctx.dhcp_unbind(&mac_or_ip).await?,
+      };
+      println!("{}", tabled::Table::new(&list));
+    }
+    Commands::Lan { action: LanAction::Config { action } } => {
+      let config = match action {
+        LanConfigAction::Show => ctx.lan_dhcp_config().await?,
+        LanConfigAction::Set { lan_ip, subnet_mask, server_enable, pool, lease_time, gateway, dns } => {
+          let mut config = ctx.lan_dhcp_config().await?;
+          if let Some(lan_ip) = lan_ip {
+            config.lan_ip = lan_ip;
+          }
+          if let Some(subnet_mask) = subnet_mask {
+            config.subnet_mask = subnet_mask;
+          }
+          if let Some(server_enable) = server_enable {
+            config.server_enable = server_enable;
+          }
+          if let Some(HostRange(start, end)) = pool {
+            config.pool_start = start;
+            config.pool_end = end;
+          }
+          if let Some(lease_time) = lease_time {
+            config.lease_time = lease_time.as_secs().try_into()?;
+          }
+          if let Some(gateway) = gateway {
+            config.gateway = gateway;
+          }
+          if let Some(dns) = dns {
+            config = config.with_dns(&dns);
+          }
+          ctx.lan_dhcp_set(&config).await?
+        },
+      };
+      println!("{}", tabled::Table::new([config]));
+    }
+    Commands::Wlan { action } => {
+      match action {
+        WlanAction::Set { ssid: key, name, passphrase } => {
+          let mut list = ctx.wlan_ssids().await?;
+          if name.is_some() || passphrase.is_some() {
+            let (_, current) = onu::wlan::find_ssid(&list, &key).ok_or_else(|| anyhow::format_err!("cannot find ssid {}", key))?;
+            let ssid = onu::WlanSsid {
+              ssid: name.unwrap_or_else(|| current.ssid.clone()),
+              passphrase: passphrase.unwrap_or_else(|| current.passphrase.clone()),
+              ..current.clone()
+            };
+            list = ctx.wlan_ssid_apply(&key, ssid).await?;
+          }
+          println!("{}", tabled::Table::new(&list));
+        },
+        WlanAction::Channel { radio, channel } => {
+          let channel = match channel.as_str() {
+            "auto" => 0,
+            c => c.parse()?,
+          };
+          let list = ctx.wlan_set_channel(&radio, channel).await?;
+          println!("{}", tabled::Table::new(&list));
+        },
+        WlanAction::Survey { radio: key, apply } => {
+          let (radio, neighbors) = ctx.wlan_site_survey(&key).await?;
+          println!("{}", tabled::Table::new(&neighbors));
+          let ranks = onu::survey::rank_channels(&radio, &neighbors);
+          println!("{}", tabled::Table::new(&ranks));
+          let Some(best) = ranks.first() else { return Ok(()) };
+          if best.channel == radio.channel {
+            println!("{} is already on the least crowded channel {}", radio.band, best.channel);
+          } else if apply {
+            let list = ctx.wlan_set_channel(&key, best.channel).await?;
+            println!("{}", tabled::Table::new(&list));
+          } else {
+            println!("suggest channel {} for {} (now {}), run again with --apply to switch", best.channel, radio.band, radio.channel);
+          }
+        },
+      }
+    }
+    Commands::GuestWifi { state, action } => {
+      let state = match state {
+        Some(state) => state,
+        None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".onu-guest-wifi.json"))
+          .ok_or_else(|| anyhow::anyhow!("HOME is not set, pass --state"))?,
+      };
+      let mut leases = onu::guest::GuestLeases::load(&state)?;
+      match action {
+        GuestWifiAction::On { ttl, ssid } => {
+          let guest = ctx.guest_wifi_on(ssid.as_deref(), *ttl).await?;
+          leases.insert(&guest);
+          leases.save(&state)?;
+          println!("{}", guest.qr_code()?);
+          println!("SSID: {}", guest.ssid.ssid);
+          println!("passphrase: {}", guest.ssid.passphrase);
+          println!("until {}, keep this running or run `guest-wifi gc` after that", humantime::format_rfc3339_seconds(guest.expires_at));
+          // the router allows only one admin session, do not hold it while waiting
+          ctx.logout().await?;
+          let wait = guest.expires_at.duration_since(std::time::SystemTime::now()).unwrap_or_default();
+          tokio::time::sleep(wait).await;
+          let (username, password) = credentials();
+          let interval = std::time::Duration::from_secs(5);
+          ctx.login_wait(&username, &password, std::time::Duration::from_secs(600), interval).await?;
+          let mut leases = onu::guest::GuestLeases::load(&state)?;
+          for lease in ctx.guest_wifi_gc(&mut leases, std::time::SystemTime::now()).await? {
+            println!("{} is off", lease.ssid);
+          }
+          leases.save(&state)?;
+        },
+        GuestWifiAction::Off { ssid } => {
+          let list = ctx.guest_wifi_off(ssid.as_deref()).await?;
+          leases.retain_enabled(&list);
+          leases.save(&state)?;
+          println!("{}", tabled::Table::new(&list));
+        },
+        GuestWifiAction::Gc => {
+          for lease in ctx.guest_wifi_gc(&mut leases, std::time::SystemTime::now()).await? {
+            println!("{} is off", lease.ssid);
+          }
+          leases.save(&state)?;
         },
       }
     }
@@ -201,7 +801,22 @@ async fn main() -> Result<()> {
   Ok(())
 }
 
-#[derive(Debug, tabled::Tabled)]
+fn print_checks(checks: &[onu::probe::RuleCheck]) {
+  let mut builder = tabled::builder::Builder::new();
+  builder.push_record(["name", "target", "probes", "dead"]);
+  for check in checks {
+    let probes = check.probes.iter().map(|(protocol, port, r)| format!("{}/{} {}", port, protocol, r)).collect::<Vec<_>>();
+    builder.push_record([
+      check.param.name.clone(),
+      check.addr.map(|a| a.to_string()).unwrap_or_else(|| "unresolved".to_string()),
+      probes.join("\n"),
+      check.is_dead().to_string(),
+    ]);
+  }
+  println!("{}", builder.build());
+}
+
+#[derive(Debug, serde::Serialize, tabled::Tabled)]
 struct PortForwardingParamView {
   enabled: bool,
   name: String,
@@ -233,18 +848,39 @@ impl From<PortForwardingParam> for PortForwardingParamView {
 mod test{
   use super::*;
 
+#[test]
+fn test_external_port() {
+  assert!(matches!("8080".parse(), Ok(ExternalPort::Fixed(PortRange(8080, 8080)))));
+  assert!(matches!("auto".parse(), Ok(ExternalPort::Auto(PortRange(20000, 29999)))));
+  assert!(matches!("auto:30000:30100".parse(), Ok(ExternalPort::Auto(PortRange(30000, 30100)))));
+  assert!("auto:x".parse::<ExternalPort>().is_err());
+  assert!("auto:9000:8000".parse::<ExternalPort>().is_err());
+  assert!("9000:8000".parse::<PortRange>().is_err());
+}
+
 #[tokio::test]
 async fn test_wan6_info() -> Result<()> {
-  dotenvy::dotenv().ok();
   flexi_logger::Logger::try_with_env_or_str("info")?.start().ok();
-  let mut ctx = ctx("http://192.168.1.1").await?;
+  let (_mock, mut ctx) = onu::mock::logged_in(onu::mock::MockConfig::default()).await?;
   let wan6_info = ctx.wan6_info().await?;
   info!("wan6_info: {:?}", wan6_info);
   let info_json = wan6_info.iter().map(serde_json::to_value).collect::<Result<Vec<_>,_>>()?;
   info!("info_json: {:?}", info_json);
   let j_t_c = json_to_csv(&info_json)?;
   info!("j_t_c: {:?}", j_t_c);
+  assert_eq!(j_t_c.len(), wan6_info.len() + 1);
   println!("{}", csv_to_table(&j_t_c));
   Ok(())
 }
+
+#[tokio::test]
+async fn test_wanc_or_default() -> Result<()> {
+  let (_mock, mut ctx) = onu::mock::logged_in(onu::mock::MockConfig::default()).await?;
+  let first = onu::mock::MockConfig::default().wanc[0].view_name.clone();
+  assert_eq!(wanc_or_default(&mut ctx, None).await?, first);
+  assert_eq!(wanc_or_default(&mut ctx, Some("x".to_string())).await?, "x");
+  let (_mock, mut ctx) = onu::mock::logged_in(onu::mock::MockConfig { wanc: Vec::new(), ..Default::default() }).await?;
+  assert!(wanc_or_default(&mut ctx, None).await.is_err());
+  Ok(())
+}
 }
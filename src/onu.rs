@@ -1,8 +1,32 @@
 use std::{collections::HashMap, path::{Path, PathBuf}};
 
-use anyhow::Result;
 use select::predicate::Predicate;
 
+pub mod backup;
+mod dhcp;
+mod dmz;
+pub mod error;
+mod expiry;
+pub mod guest;
+mod login;
+pub mod mock;
+pub mod probe;
+pub mod survey;
+pub mod sync;
+mod target;
+mod upnp;
+pub mod validate;
+pub mod wlan;
+
+pub use dhcp::{DhcpBinding, LanDhcpConfig};
+pub use dmz::DmzParam;
+pub use error::{OnuError, Result};
+pub use expiry::{GcReport, EXPIRES_TAG};
+pub use login::LoginProtocol;
+pub use target::{local_macs, PortForwardingTarget};
+pub use upnp::{UPnPMapping, UPnPStatus};
+pub use wlan::{WlanClient, WlanRadio, WlanSsid};
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Session {
   /// parsing from line
@@ -43,7 +67,21 @@ mod serde_str_as_u32 {
     D: Deserializer<'de>,
   {
     let s = String::deserialize(deserializer)?;
-    Ok(s.parse().map_err(serde::de::Error::custom)?)
+    s.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+/// this mod is a serde helper that parse json string "-45" to rust i32 -45
+mod serde_str_as_i32 {
+  use serde::{self, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(b: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&b.to_string())
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
   }
 }
 
@@ -74,7 +112,7 @@ fn display_debug<T: std::fmt::Debug>(o: &T) -> String {
 fn display_option<T: std::fmt::Display>(o: &Option<T>) -> String {
   match o {
     Some(s) => format!("{}", s),
-    None => format!(""),
+    None => String::new(),
   }
 }
 
@@ -254,7 +292,63 @@ pub enum PortForwardingAction {
   Delete(u32), DeleteByName(String),
 }
 
+/// identity of a rule that does not move with its `IF_INDEX`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortForwardingFingerprint {
+  pub name: String,
+  /// `remote_port_min..=remote_port_max`
+  pub remote_port: (u32, u32),
+  /// `local_port_min..=local_port_max`
+  pub local_port: (u32, u32),
+  /// `local_mac` if `enable_local_mac`, otherwise `local_addr`
+  pub target: String,
+}
+impl std::fmt::Display for PortForwardingFingerprint {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let range = |(min, max): (u32, u32)| if min == max { min.to_string() } else { format!("{}-{}", min, max) };
+    write!(f, "{:?} {} -> {}:{}", self.name, range(self.remote_port), self.target, range(self.local_port))
+  }
+}
+
+/// select a rule of `Context::port_forwarding_list` by `IF_INDEX`, by name or by fingerprint
 #[derive(Debug, Clone, PartialEq)]
+pub enum PortForwardingSelector {
+  Index(u32),
+  Name(String),
+  Fingerprint(PortForwardingFingerprint),
+}
+impl std::fmt::Display for PortForwardingSelector {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PortForwardingSelector::Index(i) => write!(f, "#{}", i),
+      PortForwardingSelector::Name(s) => write!(f, "{:?}", s),
+      PortForwardingSelector::Fingerprint(fp) => write!(f, "{}", fp),
+    }
+  }
+}
+impl std::str::FromStr for PortForwardingSelector {
+  type Err = &'static str;
+  /// a number is taken as index, otherwise as name
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.parse::<u32>() {
+      Ok(i) => Ok(Self::Index(i)),
+      Err(_) => Ok(Self::Name(s.to_string())),
+    }
+  }
+}
+
+impl PortForwardingSelector {
+  pub fn find<'a>(&self, list: &'a [PortForwardingParam]) -> Option<(u32, &'a PortForwardingParam)> {
+    match self {
+      PortForwardingSelector::Index(i) => list.get(*i as usize).map(|p| (*i, p)),
+      PortForwardingSelector::Name(name) => list.iter().enumerate().find(|(_, p)| &p.name == name).map(|(i, p)| (i as u32, p)),
+      PortForwardingSelector::Fingerprint(fp) => list.iter().enumerate().find(|(_, p)| &p.fingerprint() == fp).map(|(i, p)| (i as u32, p)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PortForwardingHost {
   /// InternalHost, with MacEnable=false
   Host(String),
@@ -294,10 +388,7 @@ impl PortForwardingHost {
     }
   }
   pub fn is_mac(&self) -> bool {
-    match self {
-      PortForwardingHost::Mac(_) => true,
-      _ => false,
-    }
+    matches!(self, PortForwardingHost::Mac(_))
   }
 }
 
@@ -314,6 +405,17 @@ pub enum PortForwardingPort {
   Multiple { remote: (u32, u32), local: (u32, u32) },
 }
 
+impl PortForwardingPort {
+  /// `(remote_port_min, remote_port_max), (local_port_min, local_port_max)`
+  pub fn ranges(&self) -> ((u32, u32), (u32, u32)) {
+    match *self {
+      PortForwardingPort::Simple(p) => ((p, p), (p, p)),
+      PortForwardingPort::Transform { remote, local } => ((remote, remote), (local, local)),
+      PortForwardingPort::Multiple { remote, local } => (remote, local),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
 pub struct PortForwardingParam {
   /// "0": true, "1": false
@@ -367,7 +469,132 @@ pub struct PortForwardingParam {
   pub port_map_creator: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+impl PortForwardingParam {
+  pub fn fingerprint(&self) -> PortForwardingFingerprint {
+    let target = if self.enable_local_mac { &self.local_mac } else { &self.local_addr };
+    PortForwardingFingerprint {
+      name: self.name.clone(),
+      remote_port: (self.remote_port_min, self.remote_port_max),
+      local_port: (self.local_port_min, self.local_port_max),
+      target: target.as_deref().filter(|t| *t != "NULL").unwrap_or_default().to_string(),
+    }
+  }
+
+  /// an enabled TCP/UDP rule forwarding `port` of `wan_interface` to `lan`, from any remote host,
+  /// change the rest with `with_*`
+  pub fn new(name: &str, wan_interface: &str, lan: PortForwardingHost, port: PortForwardingPort) -> Self {
+    let ((remote_port_min, remote_port_max), (local_port_min, local_port_max)) = port.ranges();
+    Self {
+      enable: true,
+      name: name.to_string(),
+      protocol: PortForwardingProtocol::Both,
+      wan_interface: wan_interface.to_string(),
+      remote_addr_min: None,
+      remote_addr_max: None,
+      remote_port_min,
+      remote_port_max,
+      local_addr: lan.as_host().map(ToString::to_string),
+      local_mac: lan.as_mac().map(ToString::to_string),
+      enable_local_mac: lan.is_mac(),
+      local_port_min,
+      local_port_max,
+      description: None,
+      port_map_creator: None,
+      lease_duration: None,
+    }
+  }
+
+  pub fn with_protocol(mut self, protocol: PortForwardingProtocol) -> Self {
+    self.protocol = protocol;
+    self
+  }
+
+  /// only accept connections from `min..=max`, e.g. the egress range of an office
+  pub fn with_remote_host(mut self, min: &str, max: &str) -> Self {
+    self.remote_addr_min = Some(min.to_string());
+    self.remote_addr_max = Some(max.to_string());
+    self
+  }
+
+  pub fn with_description(mut self, description: &str) -> Self {
+    self.description = Some(description.to_string());
+    self
+  }
+
+  pub fn with_enable(mut self, enable: bool) -> Self {
+    self.enable = enable;
+    self
+  }
+}
+
+/// fields to change by `Context::port_forwarding_edit`, `None` keeps the current value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortForwardingUpdate {
+  pub enable: Option<bool>,
+  pub name: Option<String>,
+  pub protocol: Option<PortForwardingProtocol>,
+  /// "IGD.WD1.***", see `WancInfo::view_name`
+  pub wan_interface: Option<String>,
+  /// `remote_port_min..=remote_port_max`
+  pub remote_port: Option<(u32, u32)>,
+  /// `local_port_min..=local_port_max`
+  pub local_port: Option<(u32, u32)>,
+  /// switch `enable_local_mac` along with the address
+  pub lan: Option<PortForwardingHost>,
+  pub description: Option<String>,
+}
+
+impl PortForwardingUpdate {
+  pub fn apply(&self, param: &mut PortForwardingParam) {
+    if let Some(enable) = self.enable {
+      param.enable = enable;
+    }
+    if let Some(name) = &self.name {
+      param.name = name.clone();
+    }
+    if let Some(protocol) = &self.protocol {
+      param.protocol = protocol.clone();
+    }
+    if let Some(wan_interface) = &self.wan_interface {
+      param.wan_interface = wan_interface.clone();
+    }
+    if let Some((min, max)) = self.remote_port {
+      param.remote_port_min = min;
+      param.remote_port_max = max;
+    }
+    if let Some((min, max)) = self.local_port {
+      param.local_port_min = min;
+      param.local_port_max = max;
+    }
+    match &self.lan {
+      Some(PortForwardingHost::Host(addr)) => {
+        param.local_addr = Some(addr.clone());
+        param.enable_local_mac = false;
+      }
+      Some(PortForwardingHost::Mac(mac)) => {
+        param.local_mac = Some(mac.clone());
+        param.enable_local_mac = true;
+      }
+      None => {}
+    }
+    if let Some(description) = &self.description {
+      param.description = Some(description.clone());
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortForwardingRequest {
+  #[serde(rename="IF_ACTION")]
+  action: &'static str,
+  #[serde(rename="IF_INDEX")]
+  index: i32,
+  #[serde(flatten)]
+  params: PortForwardingParam,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ApiResult {
   /// 'SUCC' for success, "" might be not presented
   pub error_str: String,
@@ -375,13 +602,23 @@ pub struct ApiResult {
   pub error_type: String,
 }
 impl ApiResult {
+  /// lenient, a response without `IF_ERRORSTR` counts as success with a warning
   pub fn is_success(&self) -> bool {
-    if self.error_str == "" {
+    if self.error_str.is_empty() {
       warn!("maybe error: {:?}", self);
       return true
     }
     self.error_str == "SUCC"
   }
+
+  /// strict version of `is_success` for responses of POST,
+  /// which must carry `IF_ERRORSTR`, otherwise `OnuError::Router`
+  pub fn check(self) -> Result<()> {
+    if self.error_str == "SUCC" {
+      return Ok(())
+    }
+    Err(self.into())
+  }
 }
 
 /// parsing from `<div>{text}</div>` or `<div><input type="text" value="{text}"></div>`
@@ -396,29 +633,84 @@ fn parse_node_text(node: select::node::Node<'_>) -> String {
 fn parse_transfer_meaning(resp: &str, field: &str) -> Option<String> {
   let value = resp
     .split(&format!("Transfer_meaning('{}',", field)).skip(1).last()?
-    .splitn(2, ')').nth(0)?
-    .trim().strip_prefix('\'')?.strip_suffix('\'')?
-    .replace("\\x2d", "\x2d")
-    .replace("\\x2e", "\x2e")
-    .replace("\\x3a", "\x3a")
-    .replace("\\x5f", "\x5f")
-    .to_string();
-  Some(value)
+    .split(')').next()?
+    .trim().strip_prefix('\'')?.strip_suffix('\'')?;
+  Some(unescape_hex(value))
+}
+
+/// decode the `\xHH` escapes the router uses for punctuation, e.g. `\x2e` => `.`,
+/// non-ascii names come as the escaped bytes of their utf-8, e.g. `\xe5\xae\xb6` => `家`
+fn unescape_hex(s: &str) -> String {
+  let mut result = Vec::with_capacity(s.len());
+  let mut rest = s;
+  while let Some(pos) = rest.find("\\x") {
+    result.extend_from_slice(&rest.as_bytes()[..pos]);
+    let hex = rest.get(pos+2..pos+4)
+      .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+      .and_then(|h| u8::from_str_radix(h, 16).ok());
+    match hex {
+      Some(c) => {
+        result.push(c);
+        rest = &rest[pos+4..];
+      }
+      None => {
+        result.extend_from_slice(b"\\x");
+        rest = &rest[pos+2..];
+      }
+    }
+  }
+  result.extend_from_slice(rest.as_bytes());
+  String::from_utf8_lossy(&result).into_owned()
+}
+
+/// parsing kv from every `space_0` table
+/// ```html
+/// <div class="space_0">
+/// <table id="TestContent" class="infor" width="410" border="0" cellpadding="0" cellspacing="1" bgcolor="#979797">
+/// <tr class="white_1">
+/// <td class="tdleft_1">模式</td>
+/// <td class="tdright">PPPoE</td>
+/// </tr>
+/// ...
+/// </table>
+/// </div>
+/// ```
+pub fn parse_space0_tables(resp: &str) -> Result<Vec<HashMap<String, String>>> {
+  use select::predicate::{Class, Name};
+  let dom = select::document::Document::from(resp);
+  let mut result = Vec::new();
+  for table in dom.find(Name("div").and(Class("space_0"))) {
+    let mut kv = HashMap::new();
+    for tr in table.find(Name("tr")) {
+      let mut td = tr.find(Name("td")).map(|i| parse_node_text(i).trim().to_string());
+      kv.entry(td.next().unwrap_or_default()).or_insert(td.next().unwrap_or_default());
+    }
+    result.push(kv);
+  }
+  Ok(result)
+}
+
+/// the login page carries `getObj("Frm_Logintoken").value = "4";`,
+/// the router serves it for every page once the session is gone
+fn is_login_page(resp: &str) -> bool {
+  resp.contains(r#"getObj("Frm_Logintoken")"#) || resp.contains("_tag=login_entry")
 }
 
 pub struct Request<'a> {
   session: &'a mut Option<Session>,
   cache_path: Option<&'a Path>,
   request: reqwest::RequestBuilder,
+  /// set by `Request::form`
+  is_form: bool,
 }
 
 impl<'a> Request<'a> {
-  fn parse_session(resp: &str) -> Option<Session> {
+  pub fn parse_session(resp: &str) -> Option<Session> {
     let session_token = resp.split("var session_token = ").skip(1).last()?
-      .splitn(3, '"').nth(1)?;
+      .split('"').nth(1)?;
     let url_next = resp
       .split("function getURL(){var ret = ").nth(1)?
-      .splitn(3, '"').nth(1)?;
+      .split('"').nth(1)?;
     debug!("session_token: {}", session_token);
     Some(Session {
       url_next: url_next.to_string(),
@@ -426,6 +718,7 @@ impl<'a> Request<'a> {
     })
   }
 
+  /// returns `OnuError::SessionExpired` when the login page is served instead
   pub async fn send(self) -> Result<(ApiResult, String)> {
     let (client, request) = self.request.build_split();
     let request = request?;
@@ -434,15 +727,22 @@ impl<'a> Request<'a> {
     let url = resp.url().to_string();
     let text = resp.text().await?;
     if let Some(cache_path) = self.cache_path {
-      std::fs::write(&cache_path, &text)?;
+      std::fs::write(cache_path, &text)?;
       debug!("cache: {} => {}", url, cache_path.display());
     }
+    let err = Self::parse_api_result(&text);
+    if is_login_page(&text) {
+      warn!("session expired: {url}");
+      *self.session = None;
+      return Err(OnuError::SessionExpired { url });
+    }
     if let Some(session) = Self::parse_session(&text) {
       debug!("update session: {}", session.session_token);
       *self.session = Some(session);
     }
-    let err = Self::parse_api_result(&text);
-    if !err.is_success() {
+    // GET pages may carry a leftover `IF_ERRORSTR` too, but only the reply to a form
+    // tells whether the change was applied
+    if self.is_form && !err.is_success() {
       error!("request {url} failed: {err:?}");
     }
     Ok((err, text))
@@ -456,6 +756,7 @@ impl<'a> Request<'a> {
       #[serde(rename = "_SESSION_TOKEN")]
       session_token: String,
     }
+    self.is_form = true;
     self.request = match self.session {
       Some(session) => {
         let data = WithSessionToken {
@@ -472,7 +773,7 @@ impl<'a> Request<'a> {
     self
   }
 
-  fn parse_api_result(resp: &str) -> ApiResult {
+  pub fn parse_api_result(resp: &str) -> ApiResult {
     let error_str = parse_transfer_meaning(resp, "IF_ERRORSTR").unwrap_or_default();
     let error_param = parse_transfer_meaning(resp, "IF_ERRORPARAM").unwrap_or_default();
     let error_type = parse_transfer_meaning(resp, "IF_ERRORTYPE").unwrap_or_default();
@@ -494,6 +795,44 @@ pub struct Context {
   pub _client: reqwest::Client,
   /// saved session_token, the session_token would be changed even in GET method
   pub session: Option<Session>,
+  /// username and password of last `Context::login`, used to login again when session expired
+  credentials: Option<(String, String)>,
+  /// `None` to detect from the landing page on every login
+  pub login_protocol: Option<LoginProtocol>,
+  /// logout on drop when still logged in, since the router allows only one admin session
+  pub auto_logout: bool,
+  /// checks before submitting a port forwarding rule, `None` submits whatever given
+  pub validator: Option<validate::Validator>,
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    let Some(session) = self.session.take() else { return };
+    if !self.auto_logout {
+      return
+    }
+    // best effort, the runtime might be shutting down
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+      let request = self._client.post(self.base_url()).form(&LogoutParam::new(session));
+      handle.spawn(async move {
+        if let Err(e) = request.send().await {
+          warn!("logout on drop failed: {}", e);
+        }
+      });
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LogoutParam {
+  logout: String,
+  #[serde(rename = "_SESSION_TOKEN")]
+  session_token: String,
+}
+impl LogoutParam {
+  fn new(session: Session) -> Self {
+    Self { logout: "1".to_string(), session_token: session.session_token }
+  }
 }
 
 impl Context {
@@ -503,6 +842,10 @@ impl Context {
       _client: reqwest::Client::new(),
       session: None,
       cache_path: None,
+      credentials: None,
+      login_protocol: None,
+      auto_logout: true,
+      validator: Some(validate::Validator::default()),
     }
   }
 
@@ -512,86 +855,102 @@ impl Context {
     format!("{}/{}{}", self.base_url, self.session.as_ref().map(|s| s.url_next.as_str()).unwrap_or("getpage.gch?pid=1002&nextpage="), page)
   }
 
-  pub fn get(&mut self, page: &str) -> Request {
+  pub fn get(&mut self, page: &str) -> Request<'_> {
     let url = self.next_url(page);
     Request {
       session: &mut self.session,
       cache_path: self.cache_path.as_deref(),
       request: self._client.get(url),
+      is_form: false,
     }
   }
 
-  pub fn post(&mut self, page: &str) -> Request {
+  /// a POST is never replayed on `OnuError::SessionExpired`, since the router might have applied it
+  pub fn post(&mut self, page: &str) -> Request<'_> {
     let url = self.next_url(page);
     Request {
       session: &mut self.session,
       cache_path: self.cache_path.as_deref(),
       request: self._client.post(url),
+      is_form: false,
     }
   }
 
   pub async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+    if self.session.is_some() {
+      // our own session would conflict with the new one
+      self.logout().await.ok();
+    }
     let session = self.init_session(username, password).await?;
     self.session = Some(session);
+    self.credentials = Some((username.to_string(), password.to_string()));
     Ok(())
   }
 
-  pub async fn init_session(&self, username: &str, password: &str) -> Result<Session> {
-    #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
-    #[serde(rename_all = "snake_case")]
-    pub enum LoginAction {
-      Login,
-    }
-    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
-    pub struct LoginParam {
-      freshnum: String,
-      action: LoginAction,
-      /// this is come from line
-      /// `getObj("Frm_Logintoken").value = "4";`
-      #[serde(rename = "Frm_Logintoken")]
-      login_token: String,
-      #[serde(rename = "Username")]
-      username: String,
-      #[serde(rename = "Password")]
-      password: String,
+  /// keep retrying on `OnuError::SessionConflict` every `interval` until `wait` elapsed,
+  /// e.g. the web UI session would time out in a few minutes
+  pub async fn login_wait(&mut self, username: &str, password: &str, wait: std::time::Duration, interval: std::time::Duration) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+      match self.login(username, password).await {
+        Err(OnuError::SessionConflict { errmsg }) if tokio::time::Instant::now() + interval <= deadline => {
+          info!("{}, retry in {:?}", errmsg, interval);
+          tokio::time::sleep(interval).await;
+        }
+        result => return result,
+      }
     }
-    let client = self._client.clone();
-    let resp = client.get(self.base_url()).send().await?.text().await?;
-    let login_token = resp
-      .split(r#"getObj("Frm_Logintoken").value = "#).nth(1).unwrap_or(r#""1""#)
-      .splitn(3, '"').nth(1).ok_or_else(|| anyhow::format_err!("login_token parse failed"))?;
-
-    debug!("login_token: {}", login_token);
-    let login_param = LoginParam {
-      freshnum: "".to_string(),
-      action: LoginAction::Login,
-      login_token: login_token.to_string(),
-      username: username.to_string(),
-      password: password.to_string(),
-    };
+  }
+
+  /// end the admin session so that others (e.g. the web UI) could login,
+  /// the credentials are forgotten so no more relogin happens
+  pub async fn logout(&mut self) -> Result<()> {
+    self.credentials = None;
+    let Some(session) = self.session.take() else { return Ok(()) };
+    self._client.post(self.base_url()).form(&LogoutParam::new(session)).send().await?;
+    Ok(())
+  }
+
+  /// login again with the credentials of last `Context::login`
+  pub async fn relogin(&mut self) -> Result<()> {
+    let (username, password) = self.credentials.clone()
+      .ok_or_else(|| OnuError::InvalidRequest("relogin before login".to_string()))?;
+    info!("session expired, login again as {}", username);
+    self.session = Some(self.init_session(&username, &password).await?);
+    Ok(())
+  }
+
+  /// GET `page`, login again and replay once if the session has expired,
+  /// use `Context::get` directly to handle `OnuError::SessionExpired` by yourself
+  pub async fn fetch(&mut self, page: &str) -> Result<(ApiResult, String)> {
+    match self.get(page).send().await {
+      Err(e) if e.is_session_expired() && self.credentials.is_some() => {
+        self.relogin().await?;
+        self.get(page).send().await
+      }
+      result => result,
+    }
+  }
 
-    debug!("{:?}", login_param);
-    let resp = client.post(self.base_url()).form(&login_param).send().await?.text().await?;
-    if resp.len() > 0 && resp.find(r#"<iframe width="808px" height="67px" src="top.gch" name="topFrame" scrolling="no" frameborder="0" id="topFrame"></iframe>"#).is_none() {
-      // parse error message
-      // `getObj("errmsg").innerHTML = "用户信息有误，请重新输入。";`
-      debug!("content length: {}", resp.len());
-      let errmsg = resp
-        .replace("function SetDisabled()\n{\ngetObj(\"errmsg\").innerHTML", "function SetDisabled()\n{\ngetObj(\"errmsg\") .innerHTML")
-        .split(r#"getObj("errmsg").innerHTML = "#).nth(1).unwrap_or(r#""login might failed""#)
-        .splitn(3, '"').nth(1).ok_or_else(|| anyhow::format_err!("errmsg parse failed"))?
-        .to_string();
-      error!("errmsg: {}", errmsg);
-      anyhow::bail!("login failed");
+  pub async fn init_session(&self, username: &str, password: &str) -> Result<Session> {
+    let client = self._client.clone();
+    let landing = client.get(self.base_url()).send().await?.text().await?;
+    let protocol = self.login_protocol.unwrap_or_else(|| LoginProtocol::detect(&landing));
+    debug!("login protocol: {:?}", protocol);
+    match protocol {
+      LoginProtocol::Legacy | LoginProtocol::SaltedSha256 =>
+        self.login_form(&landing, username, password, protocol).await?,
+      LoginProtocol::LoginEntry =>
+        self.login_entry(&landing, username, password).await?,
     }
 
     let result = client.get(self.template_url()).send().await?.text().await?;
     let session_token = result
-      .split("var session_token = ").nth(1).ok_or_else(|| anyhow::format_err!("session_token not found"))?
-      .splitn(3, '"').nth(1).ok_or_else(|| anyhow::format_err!("session_token parse failed"))?;
+      .split("var session_token = ").nth(1)
+      .and_then(|s| s.split('"').nth(1)).ok_or_else(|| OnuError::parse("template.gch", "session_token"))?;
     let url_next = result
-      .split("function getURL(){var ret = ").nth(1).ok_or_else(|| anyhow::format_err!("url_next not found"))?
-      .splitn(3, '"').nth(1).ok_or_else(|| anyhow::format_err!("url_next parse failed"))?;
+      .split("function getURL(){var ret = ").nth(1)
+      .and_then(|s| s.split('"').nth(1)).ok_or_else(|| OnuError::parse("template.gch", "getURL"))?;
     debug!("session_token: {}", session_token);
     Ok(Session {
       url_next: url_next.to_string(),
@@ -599,29 +958,9 @@ impl Context {
     })
   }
 
-  pub async fn wan_info(&mut self) -> Result<Vec<WanInfo>> {
-    use select::predicate::{Class, Name};
-    let (_, resp) = self.get("status_ethwan_if_t.gch").send().await?;
-    let dom = select::document::Document::from_read(resp.as_bytes())?;
+  pub fn parse_wan_info(resp: &str) -> Result<Vec<WanInfo>> {
     let mut result = Vec::new();
-    for table in dom.find(Name("div").and(Class("space_0"))) {
-      // parse kv from
-      // ```html
-      // <div class="space_0">
-      // <table id="TestContent" class="infor" width="410" border="0" cellpadding="0" cellspacing="1" bgcolor="#979797">
-      // <tr class="white_1">
-      // <td class="tdleft_1">模式</td>
-      // <td class="tdright">PPPoE</td>
-      // </tr>
-      // ...
-      // </table>
-      // </div>
-      // ```
-      let mut kv = HashMap::new();
-      for tr in table.find(Name("tr")) {
-        let mut td = tr.find(Name("td")).map(|i| parse_node_text(i).trim().to_string());
-        kv.entry(td.next().unwrap_or_default()).or_insert(td.next().unwrap_or_default());
-      }
+    for kv in parse_space0_tables(resp)? {
       let wan_info = match kv.get("模式").map(String::as_str) {
         Some("PPPoE") => {
           let ip_info = WanIpInfo::from_dict(&kv);
@@ -649,7 +988,7 @@ impl Context {
         },
         _ => {
           error!("unknown wan_info: {:?}", kv);
-          anyhow::bail!("unknown wan_info");
+          return Err(OnuError::parse("status_ethwan_if_t.gch", "模式"));
         }
       };
       result.push(wan_info);
@@ -657,27 +996,36 @@ impl Context {
     Ok(result)
   }
 
-  pub async fn lan_info(&mut self) -> Result<Vec<LanInfo>> {
-    let (_, resp) = self.get("status_ethlan_dhcp_info_t.gch").send().await?;
-    let count = parse_transfer_meaning(resp.as_str(), "IF_INSTNUM").unwrap_or_default()
-      .parse::<usize>().map_err(|_| anyhow::format_err!("parse IF_INSTNUM"))?;
+  pub async fn wan_info(&mut self) -> Result<Vec<WanInfo>> {
+    let (_, resp) = self.fetch("status_ethwan_if_t.gch").await?;
+    Self::parse_wan_info(&resp)
+  }
+
+  pub fn parse_lan_info(resp: &str) -> Result<Vec<LanInfo>> {
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse("status_ethlan_dhcp_info_t.gch", "IF_INSTNUM"))?;
     let mut result = Vec::new();
     for i in 0..count {
       result.push(LanInfo {
-        name: parse_transfer_meaning(resp.as_str(), &format!("HostName{}", i)).unwrap_or_default(),
-        mac: parse_transfer_meaning(resp.as_str(), &format!("MACAddr{}", i)).unwrap_or_default(),
-        ip: parse_transfer_meaning(resp.as_str(), &format!("IPAddr{}", i)).unwrap_or_default(),
-        lease_time: parse_transfer_meaning(resp.as_str(), &format!("ExpiredTime{}", i)).unwrap_or_default(),
-        interface: parse_transfer_meaning(resp.as_str(), &format!("PhyPortName{}", i)).unwrap_or_default(),
+        name: parse_transfer_meaning(resp, &format!("HostName{}", i)).unwrap_or_default(),
+        mac: parse_transfer_meaning(resp, &format!("MACAddr{}", i)).unwrap_or_default(),
+        ip: parse_transfer_meaning(resp, &format!("IPAddr{}", i)).unwrap_or_default(),
+        lease_time: parse_transfer_meaning(resp, &format!("ExpiredTime{}", i)).unwrap_or_default(),
+        interface: parse_transfer_meaning(resp, &format!("PhyPortName{}", i)).unwrap_or_default(),
       });
     }
     Ok(result)
   }
 
-  fn parse_forwarding_list(resp: &str) -> Result<Vec<PortForwardingParam>> {
+  pub async fn lan_info(&mut self) -> Result<Vec<LanInfo>> {
+    let (_, resp) = self.fetch("status_ethlan_dhcp_info_t.gch").await?;
+    Self::parse_lan_info(&resp)
+  }
+
+  pub fn parse_forwarding_list(resp: &str) -> Result<Vec<PortForwardingParam>> {
     let mut list = Vec::new();
     let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
-      .parse::<usize>().map_err(|_| anyhow::format_err!("parse IF_INSTNUM"))?;
+      .parse::<usize>().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", "IF_INSTNUM"))?;
     for i in 0..count {
       list.push(PortForwardingParam {
         enable: parse_transfer_meaning(resp, &format!("Enable{}", i)).unwrap_or_default() == "1",
@@ -686,18 +1034,18 @@ impl Context {
           "0" => PortForwardingProtocol::TCP,
           "1" => PortForwardingProtocol::UDP,
           "2" => PortForwardingProtocol::Both,
-          _ => anyhow::bail!("unknown protocol"),
+          _ => return Err(OnuError::parse("app_virtual_conf_t.gch", format!("Protocol{}", i))),
         },
         wan_interface: parse_transfer_meaning(resp, &format!("WANCViewName{}", i)).unwrap_or_default(),
         remote_addr_min: parse_transfer_meaning(resp, &format!("MinRemoteHost{}", i)),
         remote_addr_max: parse_transfer_meaning(resp, &format!("MaxRemoteHost{}", i)),
-        remote_port_min: parse_transfer_meaning(resp, &format!("MinExtPort{}", i)).unwrap_or_default().parse().map_err(|_| anyhow::format_err!("parse MinExtPort"))?,
-        remote_port_max: parse_transfer_meaning(resp, &format!("MaxExtPort{}", i)).unwrap_or_default().parse().map_err(|_| anyhow::format_err!("parse MaxExtPort"))?,
+        remote_port_min: parse_transfer_meaning(resp, &format!("MinExtPort{}", i)).unwrap_or_default().parse().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", format!("MinExtPort{}", i)))?,
+        remote_port_max: parse_transfer_meaning(resp, &format!("MaxExtPort{}", i)).unwrap_or_default().parse().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", format!("MaxExtPort{}", i)))?,
         local_addr: parse_transfer_meaning(resp, &format!("InternalHost{}", i)),
         local_mac: parse_transfer_meaning(resp, &format!("InternalMacHost{}", i)),
         enable_local_mac: parse_transfer_meaning(resp, &format!("MacEnable{}", i)).unwrap_or_default() == "1",
-        local_port_min: parse_transfer_meaning(resp, &format!("MinIntPort{}", i)).unwrap_or_default().parse().map_err(|_| anyhow::format_err!("parse MinIntPort"))?,
-        local_port_max: parse_transfer_meaning(resp, &format!("MaxIntPort{}", i)).unwrap_or_default().parse().map_err(|_| anyhow::format_err!("parse MaxIntPort"))?,
+        local_port_min: parse_transfer_meaning(resp, &format!("MinIntPort{}", i)).unwrap_or_default().parse().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", format!("MinIntPort{}", i)))?,
+        local_port_max: parse_transfer_meaning(resp, &format!("MaxIntPort{}", i)).unwrap_or_default().parse().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", format!("MaxIntPort{}", i)))?,
         description: parse_transfer_meaning(resp, &format!("Description{}", i)),
         port_map_creator: parse_transfer_meaning(resp, &format!("PortMappCreator{}", i)),
         lease_duration: parse_transfer_meaning(resp, &format!("LeaseDuration{}", i)),
@@ -709,18 +1057,18 @@ impl Context {
 
   pub fn parse_wanc_info(resp: &str) -> Result<Vec<WancInfo>> {
     use select::predicate::{Attr, Name};
-    let mut result = select::document::Document::from_read(resp.as_bytes())?
+    let mut result = select::document::Document::from(resp)
       .find(Attr("id", "Frm_WANCViewName").descendant(Name("option"))).map(|option| {
         let name = option.text();
         let view_name = option.attr("value").unwrap_or_default().to_string();
-        let ipmode = option.attr("ipmode").unwrap_or_default().parse().map_err(|_| anyhow::format_err!("parse ipmode"))?;
-        anyhow::Ok(WancInfo {
+        let ipmode = option.attr("ipmode").unwrap_or_default().parse().map_err(|_| OnuError::parse("app_virtual_conf_t.gch", "ipmode"))?;
+        Ok(WancInfo {
           name: name.to_string(),
           view_name: view_name.to_string(),
           desc_name: String::new(),
           ipmode,
         })
-      }).collect::<Result<Vec<_>,_>>()?;
+      }).collect::<Result<Vec<_>>>()?;
 
     let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
       .parse::<usize>().unwrap_or_default();
@@ -746,74 +1094,105 @@ impl Context {
   }
 
   pub async fn wanc_info(&mut self) -> Result<Vec<WancInfo>> {
-    let (_, resp) = self.get("app_virtual_conf_t.gch").send().await?;
+    let (_, resp) = self.fetch("app_virtual_conf_t.gch").await?;
     let info = Self::parse_wanc_info(&resp)?;
     Ok(info)
   }
 
-  pub async fn wan6_info(&mut self) -> Result<Vec<Wan6Info>> {
-    use select::predicate::{Class, Name};
-    let (_, resp) = self.get("status_wanstatu_ipv6wansta_t.gch").send().await?;
-    let dom = select::document::Document::from_read(resp.as_bytes())?;
-
-    let mut result = Vec::new();
-    for table in dom.find(Name("div").and(Class("space_0"))) {
-      let mut kv = HashMap::new();
-      for tr in table.find(Name("tr")) {
-        let mut td = tr.find(Name("td")).map(|i| parse_node_text(i).trim().to_string());
-        kv.entry(td.next().unwrap_or_default()).or_insert(td.next().unwrap_or_default());
-      }
-      let info = Wan6Info {
-        name: kv.get("PPP").map(String::to_string).unwrap_or_default(),
-        gua_assignment: kv.get("GUA获取方式").map(String::to_string).unwrap_or_default(),
-        gua: kv.get("GUA").map(String::to_string).unwrap_or_default(),
-        dns_assignment: kv.get("DNS获取方式").map(String::to_string).unwrap_or_default(),
-        dns1: kv.get("DNS1").map(String::to_string).unwrap_or_default(),
-        dns2: kv.get("DNS2").map(String::to_string).unwrap_or_default(),
-        dns3: kv.get("DNS3").map(String::to_string).unwrap_or_default(),
-        wan_mac: kv.get("WAN MAC").map(String::to_string).unwrap_or_default(),
-        prefix_assignment: kv.get("前缀获取方式").map(String::to_string).unwrap_or_default(),
-        prefix: kv.get("获取前缀").map(String::to_string).unwrap_or_default(),
-        gateway_assignment: kv.get("网关获取方式").map(String::to_string).unwrap_or_default(),
-        gateway: kv.get("网关").map(String::to_string).unwrap_or_default(),
-        status: kv.get("连接状态").map(String::to_string).unwrap_or_default(),
-      };
-      result.push(info)
-    }
+  pub fn parse_wan6_info(resp: &str) -> Result<Vec<Wan6Info>> {
+    let result = parse_space0_tables(resp)?.into_iter().map(|kv| Wan6Info {
+      name: kv.get("PPP").map(String::to_string).unwrap_or_default(),
+      gua_assignment: kv.get("GUA获取方式").map(String::to_string).unwrap_or_default(),
+      gua: kv.get("GUA").map(String::to_string).unwrap_or_default(),
+      dns_assignment: kv.get("DNS获取方式").map(String::to_string).unwrap_or_default(),
+      dns1: kv.get("DNS1").map(String::to_string).unwrap_or_default(),
+      dns2: kv.get("DNS2").map(String::to_string).unwrap_or_default(),
+      dns3: kv.get("DNS3").map(String::to_string).unwrap_or_default(),
+      wan_mac: kv.get("WAN MAC").map(String::to_string).unwrap_or_default(),
+      prefix_assignment: kv.get("前缀获取方式").map(String::to_string).unwrap_or_default(),
+      prefix: kv.get("获取前缀").map(String::to_string).unwrap_or_default(),
+      gateway_assignment: kv.get("网关获取方式").map(String::to_string).unwrap_or_default(),
+      gateway: kv.get("网关").map(String::to_string).unwrap_or_default(),
+      status: kv.get("连接状态").map(String::to_string).unwrap_or_default(),
+    }).collect();
     Ok(result)
   }
 
+  pub async fn wan6_info(&mut self) -> Result<Vec<Wan6Info>> {
+    let (_, resp) = self.fetch("status_wanstatu_ipv6wansta_t.gch").await?;
+    Self::parse_wan6_info(&resp)
+  }
+
   pub async fn port_forwarding_list(&mut self) -> Result<Vec<PortForwardingParam>> {
-    let (_, resp) = self.get("app_virtual_conf_t.gch").send().await?;
+    let (_, resp) = self.fetch("app_virtual_conf_t.gch").await?;
     let list = Self::parse_forwarding_list(&resp)?;
     Ok(list)
   }
 
+  /// POST `params` to `app_virtual_conf_t.gch`, returns the list in the response.
+  ///
+  /// `target` ("apply" and "delete") is looked up in a fresh list right before the POST,
+  /// "new" and "apply" are checked by `Context::validator` against that list,
+  /// and the returned list must show the rule added, overwritten or removed
+  async fn port_forwarding_submit(&mut self, action: &'static str, target: Option<&PortForwardingSelector>, params: PortForwardingParam) -> Result<Vec<PortForwardingParam>> {
+    let before = self.port_forwarding_list().await?;
+    let (index, expected) = match target {
+      Some(target) => {
+        let (index, current) = target.find(&before)
+          .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find {} in port forwarding list", target)))?;
+        (index as i32, Some(current.fingerprint()))
+      }
+      None => (-1, None),
+    };
+    if action != "delete" {
+      if let Some(validator) = &self.validator {
+        validator.validate(&params, u32::try_from(index).ok(), &before)?;
+      }
+    }
+    let fingerprint = params.fingerprint();
+    let (err, resp) = self.post("app_virtual_conf_t.gch").form(&PortForwardingRequest {
+      action,
+      index,
+      params,
+    }).send().await?;
+    err.check()?;
+    let list = Self::parse_forwarding_list(&resp)?;
+
+    let count = |list: &[PortForwardingParam], fp: &PortForwardingFingerprint| list.iter().filter(|p| &p.fingerprint() == fp).count();
+    let confirmed = match (action, &expected) {
+      ("delete", Some(expected)) => list.len() + 1 == before.len() && count(&list, expected) + 1 == count(&before, expected),
+      ("apply", _) => list.get(index as usize).map(PortForwardingParam::fingerprint).as_ref() == Some(&fingerprint),
+      _ => count(&list, &fingerprint) == count(&before, &fingerprint) + 1,
+    };
+    if !confirmed {
+      let rule = expected.unwrap_or(fingerprint).to_string();
+      return Err(OnuError::Unconfirmed { action, rule });
+    }
+    Ok(list)
+  }
+
+  /// pick `len` free external ports inside `range` for a new rule, see `Validator::free_external_ports`
+  pub async fn port_forwarding_allocate(&mut self, protocol: &PortForwardingProtocol, wan_interface: &str, len: u32, range: (u32, u32)) -> Result<(u32, u32)> {
+    let list = self.port_forwarding_list().await?;
+    self.validator.clone().unwrap_or_default()
+      .free_external_ports(&list, protocol, wan_interface, len, range)
+      .ok_or_else(|| OnuError::InvalidRequest(format!("no {} free external ports in {}:{}", len, range.0, range.1)))
+  }
+
   /// only `PortForwardingAction::Delete` and `PortForwardingAction::DeleteByName` accepted,
   /// for other usage, see `Context::port_forwarding`
   pub async fn port_forwarding_delete(&mut self, action: PortForwardingAction) -> Result<Vec<PortForwardingParam>> {
-    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
-    #[serde(rename_all = "PascalCase")]
-    pub struct PortForwardingRequest {
-      #[serde(rename="IF_ACTION")]
-      action: &'static str,
-      #[serde(rename="IF_INDEX")]
-      index: i32,
-      #[serde(flatten)]
-      params: PortForwardingParam,
-    }
-    let index = match action {
+    let selector = match action {
       PortForwardingAction::New | PortForwardingAction::Apply(_) =>
-        anyhow::bail!("use port_forwarding instead"),
-      PortForwardingAction::Delete(i) => i,
-      PortForwardingAction::DeleteByName(name) => {
-        let list = self.port_forwarding_list().await?;
-        match list.iter().enumerate().find(|(_, p)| p.name == name) {
-          Some((i, _)) => i as _,
-          None => anyhow::bail!("cannot find name in list"),
-        }
-      },
+        return Err(OnuError::InvalidRequest("use port_forwarding instead".to_string())),
+      PortForwardingAction::Delete(i) => PortForwardingSelector::Index(i),
+      PortForwardingAction::DeleteByName(name) => PortForwardingSelector::Name(name),
     };
+    self.port_forwarding_remove(&selector).await
+  }
+
+  /// delete the rule selected by `selector` (`IF_ACTION=delete`)
+  pub async fn port_forwarding_remove(&mut self, selector: &PortForwardingSelector) -> Result<Vec<PortForwardingParam>> {
     let param = PortForwardingParam {
       enable: false,
       name: "".to_string(),
@@ -832,163 +1211,549 @@ impl Context {
       port_map_creator: None,
       lease_duration: None,
     };
-    let (err, resp) = self.post("app_virtual_conf_t.gch").form(&PortForwardingRequest {
-      action: "delete",
-      index: index as _,
-      params: param,
-    }).send().await?;
-    if !err.is_success() {
-      anyhow::bail!("port forwarding delete failed: {:?}", err);
-    }
-    let list = Self::parse_forwarding_list(&resp)?;
-    Ok(list)
+    self.port_forwarding_submit("delete", Some(selector), param).await
   }
 
   /// only `PortForwardingAction::New` and `PortForwardingAction::Apply` accepted,
   /// for other usage, see `Context::port_forwarding_delete`
-  pub async fn port_forwarding(&mut self, action: PortForwardingAction, name: &str, protocol: PortForwardingProtocol, wan: &str, lan: PortForwardingHost, port: PortForwardingPort) -> Result<Vec<PortForwardingParam>> {
-    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
-    #[serde(rename_all = "PascalCase")]
-    pub struct PortForwardingRequest {
-      #[serde(rename="IF_ACTION")]
-      action: &'static str,
-      #[serde(rename="IF_INDEX")]
-      index: i32,
-      #[serde(flatten)]
-      params: PortForwardingParam,
-    }
-    let (remote_port_min, remote_port_max, local_port_min, local_port_max) = match port {
-      PortForwardingPort::Simple(p) => (p, p, p, p),
-      PortForwardingPort::Transform { remote, local } => (remote, remote, local, local),
-      PortForwardingPort::Multiple { remote: (remote_min, remote_max), local: (local_min, local_max) } => (remote_min, remote_max, local_min, local_max),
-    };
-    let (err, resp) = self.post("app_virtual_conf_t.gch").form(&PortForwardingRequest {
-      action: match action {
-        PortForwardingAction::New => "new",
-        PortForwardingAction::Apply(_) => "apply",
-        PortForwardingAction::Delete(_) | PortForwardingAction::DeleteByName(_) =>
-          anyhow::bail!("use port_forwarding_delete instead"),
-      },
-      index: match action {
-        PortForwardingAction::Apply(i) => i as i32,
-        PortForwardingAction::New => -1,
-        _ => unreachable!(),
-      },
-      params: PortForwardingParam {
-        enable: true,
-        name: name.to_string(),
-        protocol,
-        wan_interface: wan.to_string(),
-        remote_addr_min: None,
-        remote_addr_max: None,
-        remote_port_min,
-        remote_port_max,
-        local_addr: lan.as_host().map(ToString::to_string),
-        local_mac: lan.as_mac().map(ToString::to_string),
-        enable_local_mac: lan.is_mac(),
-        local_port_min,
-        local_port_max,
-        description: None,
-        port_map_creator: None,
-        lease_duration: None,
-      },
-    }).send().await?;
-    if !err.is_success() {
-      anyhow::bail!("port forwarding failed: {:?}", err);
+  /// ```no_run
+  /// # async fn f(ctx: &mut telegram_forcast56::onu::Context) -> telegram_forcast56::onu::Result<()> {
+  /// use telegram_forcast56::onu::*;
+  /// let param = PortForwardingParam::new("rdp", "IGD.WD1.WCD3.WCPPP1", "192.168.1.4".parse().unwrap(), PortForwardingPort::Transform { remote: 13389, local: 3389 })
+  ///   .with_protocol(PortForwardingProtocol::TCP)
+  ///   .with_remote_host("203.0.113.0", "203.0.113.255")
+  ///   .with_description("office only");
+  /// ctx.port_forwarding(PortForwardingAction::New, param).await?;
+  /// # Ok(()) }
+  /// ```
+  pub async fn port_forwarding(&mut self, action: PortForwardingAction, param: PortForwardingParam) -> Result<Vec<PortForwardingParam>> {
+    match action {
+      PortForwardingAction::New => self.port_forwarding_submit("new", None, param).await,
+      PortForwardingAction::Apply(i) => self.port_forwarding_apply(&PortForwardingSelector::Index(i), param).await,
+      PortForwardingAction::Delete(_) | PortForwardingAction::DeleteByName(_) =>
+        Err(OnuError::InvalidRequest("use port_forwarding_delete instead".to_string())),
     }
-    let list = Self::parse_forwarding_list(&resp)?;
-    Ok(list)
+  }
+
+  /// overwrite the rule selected by `selector` with `param` as a whole (`IF_ACTION=apply`)
+  pub async fn port_forwarding_apply(&mut self, selector: &PortForwardingSelector, param: PortForwardingParam) -> Result<Vec<PortForwardingParam>> {
+    self.port_forwarding_submit("apply", Some(selector), param).await
+  }
+
+  /// load the current rule selected by `selector`, change only the fields given in `update`,
+  /// and submit it back with `IF_ACTION=apply`
+  pub async fn port_forwarding_edit(&mut self, selector: &PortForwardingSelector, update: &PortForwardingUpdate) -> Result<Vec<PortForwardingParam>> {
+    let list = self.port_forwarding_list().await?;
+    let (index, param) = selector.find(&list)
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find {} in port forwarding list", selector)))?;
+    // the index might have moved before the POST, follow the rule instead
+    let selector = PortForwardingSelector::Fingerprint(param.fingerprint());
+    let mut param = param.clone();
+    update.apply(&mut param);
+    debug!("edit port forwarding {}: {:?}", index, param);
+    self.port_forwarding_apply(&selector, param).await
+  }
+
+  /// turn a rule on or off without losing its settings
+  pub async fn port_forwarding_set_enable(&mut self, selector: &PortForwardingSelector, enable: bool) -> Result<Vec<PortForwardingParam>> {
+    self.port_forwarding_edit(selector, &PortForwardingUpdate { enable: Some(enable), ..Default::default() }).await
   }
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
+  use anyhow::Result;
+
+/// a POST body of `app_virtual_conf_t.gch` without any check
+fn raw_request(action: &'static str, index: i32) -> PortForwardingRequest {
+  PortForwardingRequest {
+    action,
+    index,
+    params: PortForwardingParam::new("__test_rust_onu__raw", "IGD.WD1.WCD3.WCPPP1",
+      PortForwardingHost::Host("1.1.1.1".to_string()), PortForwardingPort::Simple(1050)),
+  }
+}
 
-async fn get_ctx() -> Result<Context> {
-  dotenvy::dotenv().ok();
+async fn get_ctx() -> Result<(Context, mock::MockHandle)> {
   flexi_logger::Logger::try_with_env_or_str("info")?.start().ok();
-  let username = std::env::var("router_username").unwrap_or_else(|_| "admin".to_string());
-  let password = std::env::var("router_password").unwrap_or_else(|_| "password".to_string());
-  info!("login as {}", username);
-  let mut ctx = Context::new("http://192.168.1.1");
-  ctx.login(&username, &password).await?;
-  Ok(ctx)
+  let (mock, ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  info!("logged in as {} to {}", mock::USERNAME, mock.base_url());
+  Ok((ctx, mock))
 }
 
 #[tokio::test]
 async fn test_login() -> Result<()> {
-  let mut ctx = get_ctx().await?;
+  let (mut ctx, _mock) = get_ctx().await?;
   info!("{:?}", ctx.session);
+  assert!(ctx.session.is_some());
   let wan_info = ctx.wan_info().await?;
   info!("{:?}", wan_info);
   Ok(())
 }
 
+#[tokio::test]
+async fn test_login_failed() -> Result<()> {
+  let mock = mock::spawn(mock::MockConfig::default()).await?;
+  let mut ctx = Context::new(mock.base_url());
+  let err = ctx.login(mock::USERNAME, "wrong password").await.unwrap_err();
+  assert!(matches!(&err, OnuError::BadCredentials { errmsg } if errmsg == "用户信息有误，请重新输入。"), "{:?}", err);
+  assert!(ctx.session.is_none());
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_login_locked() -> Result<()> {
+  let mock = mock::spawn(mock::MockConfig { max_login_failures: 2, ..Default::default() }).await?;
+  let mut ctx = Context::new(mock.base_url());
+  for _ in 0..2 {
+    let err = ctx.login(mock::USERNAME, "wrong password").await.unwrap_err();
+    assert!(matches!(err, OnuError::BadCredentials { .. }), "{:?}", err);
+  }
+  let err = ctx.login(mock::USERNAME, mock::PASSWORD).await.unwrap_err();
+  assert!(matches!(err, OnuError::AccountLocked { .. }), "{:?}", err);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_router_error() -> Result<()> {
+  let (mut ctx, _mock) = get_ctx().await?;
+  let (result, _) = ctx.post("app_virtual_conf_t.gch").form(raw_request("delete", 3)).send().await?;
+  let err = result.check().unwrap_err();
+  assert!(matches!(&err, OnuError::Router { error_str, .. } if error_str == "ParamError"), "{:?}", err);
+  // a stale index is never posted
+  let err = ctx.port_forwarding_delete(PortForwardingAction::Delete(3)).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  let err = ctx.port_forwarding_delete(PortForwardingAction::New).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_login_protocols() -> Result<()> {
+  for protocol in [LoginProtocol::Legacy, LoginProtocol::SaltedSha256, LoginProtocol::LoginEntry] {
+    let mock = mock::spawn(mock::MockConfig { login_protocol: protocol, ..Default::default() }).await?;
+    let mut ctx = Context::new(mock.base_url());
+    let err = ctx.login(mock::USERNAME, "wrong password").await.unwrap_err();
+    assert!(matches!(err, OnuError::BadCredentials { .. }), "{:?}: {:?}", protocol, err);
+    ctx.login(mock::USERNAME, mock::PASSWORD).await?;
+    assert_eq!(ctx.lan_info().await?, mock.state().lock().await.config.lan);
+    // login again with the same protocol once the session expired
+    mock.state().lock().await.expire_session();
+    assert_eq!(ctx.lan_info().await?, mock.state().lock().await.config.lan);
+  }
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_login_entry_locked() -> Result<()> {
+  let mock = mock::spawn(mock::MockConfig {
+    login_protocol: LoginProtocol::LoginEntry, max_login_failures: 1, ..Default::default()
+  }).await?;
+  let mut ctx = Context::new(mock.base_url());
+  ctx.login(mock::USERNAME, "wrong password").await.unwrap_err();
+  let err = ctx.login(mock::USERNAME, mock::PASSWORD).await.unwrap_err();
+  assert!(matches!(err, OnuError::AccountLocked { .. }), "{:?}", err);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_logout() -> Result<()> {
+  let (mock, mut ctx) = mock::logged_in(mock::MockConfig { single_session: true, ..Default::default() }).await?;
+  ctx.lan_info().await?;
+
+  let mut other = Context::new(mock.base_url());
+  let err = other.login(mock::USERNAME, mock::PASSWORD).await.unwrap_err();
+  assert!(matches!(err, OnuError::SessionConflict { .. }), "{:?}", err);
+
+  ctx.logout().await?;
+  assert!(ctx.session.is_none());
+  assert!(!mock.state().lock().await.is_logged_in());
+  // no relogin after logout
+  assert!(ctx.lan_info().await.unwrap_err().is_session_expired());
+  other.login(mock::USERNAME, mock::PASSWORD).await?;
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_logout_on_drop() -> Result<()> {
+  let (mock, mut ctx) = mock::logged_in(mock::MockConfig { single_session: true, ..Default::default() }).await?;
+  ctx.lan_info().await?;
+  drop(ctx);
+  for _ in 0..50 {
+    if !mock.state().lock().await.is_logged_in() {
+      break
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+  }
+  assert!(!mock.state().lock().await.is_logged_in());
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_login_wait() -> Result<()> {
+  let (mock, _ctx) = mock::logged_in(mock::MockConfig { single_session: true, ..Default::default() }).await?;
+  let mut other = Context::new(mock.base_url());
+  let interval = std::time::Duration::from_millis(50);
+  let err = other.login_wait(mock::USERNAME, mock::PASSWORD, interval * 2, interval).await.unwrap_err();
+  assert!(matches!(err, OnuError::SessionConflict { .. }), "{:?}", err);
+
+  let state = mock.state().clone();
+  tokio::spawn(async move {
+    tokio::time::sleep(interval * 2).await;
+    state.lock().await.expire_session();
+  });
+  other.login_wait(mock::USERNAME, mock::PASSWORD, interval * 20, interval).await?;
+  Ok(())
+}
+
 #[tokio::test]
 async fn test_info() -> Result<()> {
-  let mut ctx = get_ctx().await?;
+  let (mut ctx, mock) = get_ctx().await?;
+  let config = mock.state().lock().await.config.clone();
   let wan_info = ctx.wan_info().await?;
   info!("{:?}", wan_info);
+  assert_eq!(wan_info, config.wan);
   let lan_info = ctx.lan_info().await?;
   info!("{:?}", lan_info);
+  assert_eq!(lan_info, config.lan);
+  let wanc_info = ctx.wanc_info().await?;
+  info!("{:?}", wanc_info);
+  assert_eq!(wanc_info, config.wanc);
   Ok(())
 }
 
 #[tokio::test]
 async fn test_port_forwarding() -> Result<()> {
-  let mut ctx = get_ctx().await?;
+  let (mut ctx, _mock) = get_ctx().await?;
 
   async fn clean_up(ctx: &mut Context) -> Result<()> {
-    loop {
-      let list = ctx.port_forwarding_list().await?;
-      for (i, t) in list.iter().enumerate().rev() {
-        if t.name.starts_with("__test_rust_onu__") {
-          info!("deleting {} {}", i, t.name);
-          ctx.port_forwarding_delete(PortForwardingAction::Delete(i as _)).await?;
-          continue;
-        }
+    let list = ctx.port_forwarding_list().await?;
+    for (i, t) in list.iter().enumerate().rev() {
+      if t.name.starts_with("__test_rust_onu__") {
+        info!("deleting {} {}", i, t.name);
+        ctx.port_forwarding_delete(PortForwardingAction::Delete(i as _)).await?;
       }
-      return Ok(())
     }
+    Ok(())
   }
 
   clean_up(&mut ctx).await?;
   for i in 0..10 {
     info!("adding {}", 1050+i);
-    ctx.port_forwarding(
-      PortForwardingAction::New,
+    ctx.port_forwarding(PortForwardingAction::New, PortForwardingParam::new(
       &format!("__test_rust_onu__{}", i),
-      PortForwardingProtocol::TCP,
       "IGD.WD1.WCD3.WCPPP1",
       PortForwardingHost::Host("1.1.1.1".to_string()),
-      PortForwardingPort::Simple(1050+i)).await?;
+      PortForwardingPort::Simple(1050+i)).with_protocol(PortForwardingProtocol::TCP)).await?;
   }
 
-  ctx.cache_path = Some("cache.html".into());
   let list = ctx.port_forwarding_list().await?;
-  ctx.cache_path = None;
   debug!("{:?}", list);
+  assert_eq!(list.len(), 10);
+  assert_eq!(list[3].name, "__test_rust_onu__3");
+  assert_eq!(list[3].remote_port_min, 1053);
+  assert_eq!(list[3].local_addr.as_deref(), Some("1.1.1.1"));
+
+  let list = ctx.port_forwarding(PortForwardingAction::New, PortForwardingParam::new(
+    "__test_rust_onu__office", "IGD.WD1.WCD3.WCPPP1",
+    PortForwardingHost::Host("1.1.1.1".to_string()), PortForwardingPort::Simple(1060))
+    .with_remote_host("203.0.113.0", "203.0.113.255")
+    .with_description("office only")).await?;
+  assert_eq!(list[10].protocol, PortForwardingProtocol::Both);
+  assert_eq!(list[10].remote_addr_min.as_deref(), Some("203.0.113.0"));
+  assert_eq!(list[10].remote_addr_max.as_deref(), Some("203.0.113.255"));
+  assert_eq!(list[10].description.as_deref(), Some("office only"));
+
+  let list = ctx.port_forwarding_delete(PortForwardingAction::DeleteByName("__test_rust_onu__3".to_string())).await?;
+  assert_eq!(list.len(), 10);
+  assert!(list.iter().all(|p| p.name != "__test_rust_onu__3"));
 
   clean_up(&mut ctx).await?;
+  assert!(ctx.port_forwarding_list().await?.is_empty());
   Ok(())
 }
 
-#[test]
-fn test_parse() -> Result<()> {
-  let result = std::fs::read_to_string("cache.html")?;
+#[tokio::test]
+async fn test_port_forwarding_edit() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  for (i, name) in ["web", "ssh"].iter().enumerate() {
+    ctx.port_forwarding(PortForwardingAction::New, PortForwardingParam::new(
+      name, "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.4".to_string()),
+      PortForwardingPort::Simple(8080 + i as u32)).with_protocol(PortForwardingProtocol::TCP)).await?;
+  }
+  let mut ssh = ctx.port_forwarding_list().await?[1].clone();
+  ssh.description = Some("kept".to_string());
+  ctx.port_forwarding_apply(&PortForwardingSelector::Index(1), ssh).await?;
+
+  let list = ctx.port_forwarding_edit(&PortForwardingSelector::Name("ssh".to_string()), &PortForwardingUpdate {
+    remote_port: Some((2222, 2222)),
+    lan: Some(PortForwardingHost::Mac("aa:bb:cc:dd:ee:01".to_string())),
+    ..Default::default()
+  }).await?;
+  assert_eq!(list.len(), 2);
+  assert_eq!(list[1].name, "ssh");
+  assert_eq!((list[1].remote_port_min, list[1].remote_port_max), (2222, 2222));
+  assert_eq!((list[1].local_port_min, list[1].local_port_max), (8081, 8081));
+  assert!(list[1].enable_local_mac);
+  assert_eq!(list[1].local_mac.as_deref(), Some("aa:bb:cc:dd:ee:01"));
+  assert_eq!(list[1].description.as_deref(), Some("kept"));
+  assert_eq!(list[0], ctx.port_forwarding_list().await?[0]);
+
+  let list = ctx.port_forwarding_edit(&PortForwardingSelector::Index(0), &PortForwardingUpdate {
+    protocol: Some(PortForwardingProtocol::Both),
+    description: Some("nginx".to_string()),
+    ..Default::default()
+  }).await?;
+  assert_eq!(list[0].protocol, PortForwardingProtocol::Both);
+  assert_eq!(list[0].description.as_deref(), Some("nginx"));
+  assert_eq!(list[0].remote_port_min, 8080);
+
+  let err = ctx.port_forwarding_edit(&PortForwardingSelector::Index(0), &PortForwardingUpdate {
+    remote_port: Some((2222, 2222)),
+    ..Default::default()
+  }).await.unwrap_err();
+  assert!(matches!(&err, OnuError::Validation(issues) if issues.len() == 1), "{:?}", err);
+
+  let err = ctx.port_forwarding_edit(&"nope".parse().unwrap(), &PortForwardingUpdate::default()).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  assert_eq!(mock.state().lock().await.config.port_forwarding.len(), 2);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_port_forwarding_set_enable() -> Result<()> {
+  let (mut ctx, _mock) = get_ctx().await?;
+  ctx.port_forwarding(PortForwardingAction::New, PortForwardingParam::new(
+    "rdp", "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.4".to_string()),
+    PortForwardingPort::Transform { remote: 13389, local: 3389 }).with_protocol(PortForwardingProtocol::TCP)).await?;
+  let before = ctx.port_forwarding_list().await?;
+  assert!(before[0].enable);
+
+  let list = ctx.port_forwarding_set_enable(&PortForwardingSelector::Name("rdp".to_string()), false).await?;
+  assert!(!list[0].enable);
+  assert_eq!(PortForwardingParam { enable: true, ..list[0].clone() }, before[0]);
+
+  let list = ctx.port_forwarding_set_enable(&PortForwardingSelector::Index(0), true).await?;
+  assert_eq!(list, before);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_port_forwarding_moved() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  for (i, name) in ["web", "ssh", "rdp"].iter().enumerate() {
+    ctx.port_forwarding(PortForwardingAction::New, PortForwardingParam::new(
+      name, "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.4".to_string()),
+      PortForwardingPort::Simple(8080 + i as u32))).await?;
+  }
+  let list = ctx.port_forwarding_list().await?;
+  let ssh = list[1].fingerprint();
+  assert_eq!(ssh.to_string(), r#""ssh" 8081 -> 192.168.1.4:8081"#);
+  // someone deletes "web" from the web UI meanwhile
+  mock.state().lock().await.config.port_forwarding.remove(0);
+
+  let list = ctx.port_forwarding_apply(&PortForwardingSelector::Fingerprint(ssh.clone()), PortForwardingParam {
+    description: Some("moved".to_string()),
+    ..list[1].clone()
+  }).await?;
+  assert_eq!(list[0].name, "ssh");
+  assert_eq!(list[0].description.as_deref(), Some("moved"));
+  let list = ctx.port_forwarding_remove(&PortForwardingSelector::Fingerprint(ssh.clone())).await?;
+  assert_eq!(list.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["rdp"]);
+  let err = ctx.port_forwarding_remove(&PortForwardingSelector::Fingerprint(ssh)).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_parse() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  mock.state().lock().await.config.port_forwarding = vec![PortForwardingParam {
+    enable: true,
+    name: "rdp".to_string(),
+    protocol: PortForwardingProtocol::Both,
+    wan_interface: "IGD.WD1.WCD3.WCPPP1".to_string(),
+    remote_addr_min: None,
+    remote_addr_max: None,
+    remote_port_min: 13389,
+    remote_port_max: 13389,
+    local_addr: Some("192.168.1.4".to_string()),
+    local_mac: None,
+    enable_local_mac: false,
+    local_port_min: 3389,
+    local_port_max: 3389,
+    description: None,
+    port_map_creator: None,
+    lease_duration: None,
+  }];
+  let cache_path = std::env::temp_dir().join(format!("onu_test_parse_{}.html", std::process::id()));
+  ctx.cache_path = Some(cache_path.clone());
+  ctx.port_forwarding_list().await?;
+  let result = std::fs::read_to_string(&cache_path)?;
+  std::fs::remove_file(&cache_path).ok();
   let err = Request::parse_api_result(&result);
   let list = Context::parse_forwarding_list(&result)?;
   println!("{:?}", err);
   println!("{:?}", list);
+  assert!(err.error_str.is_empty());
+  assert_eq!(list.len(), 1);
+  assert_eq!(list[0].name, "rdp");
+  assert_eq!(list[0].protocol, PortForwardingProtocol::Both);
+  assert_eq!((list[0].remote_port_min, list[0].local_port_min), (13389, 3389));
+  assert_eq!(list[0].local_addr.as_deref(), Some("192.168.1.4"));
   Ok(())
 }
 
 #[tokio::test]
 async fn test_parse_wan6() -> Result<()> {
-  let mut ctx = get_ctx().await?;
-  let _info = ctx.wan6_info().await?;
+  let (mut ctx, mock) = get_ctx().await?;
+  let info = ctx.wan6_info().await?;
+  assert_eq!(info, mock.state().lock().await.config.wan6);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_session_expired_relogin() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  let old_session = ctx.session.clone();
+  mock.state().lock().await.expire_session();
+  let lan_info = ctx.lan_info().await?;
+  assert_eq!(lan_info, mock.state().lock().await.config.lan);
+  assert!(ctx.session.is_some());
+  assert_ne!(ctx.session, old_session);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_session_expired_post() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  ctx.port_forwarding_list().await?;
+  mock.state().lock().await.expire_session();
+  // `Context::port_forwarding` would GET the list, and login again, before the POST
+  let err = ctx.post("app_virtual_conf_t.gch").form(raw_request("new", -1)).send().await.unwrap_err();
+  assert!(err.is_session_expired(), "{:?}", err);
+  assert!(ctx.session.is_none());
+  // the next GET logs in again, and the POST above has not been replayed
+  assert!(ctx.port_forwarding_list().await?.is_empty());
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_session_token_rejected() -> Result<()> {
+  let (mut ctx, mock) = get_ctx().await?;
+  ctx.port_forwarding_list().await?;
+  ctx.session.as_mut().unwrap().session_token = "stale".to_string();
+  let err = ctx.post("app_virtual_conf_t.gch").form(raw_request("new", -1)).send().await.unwrap_err();
+  assert!(err.is_session_expired(), "{:?}", err);
+  assert!(mock.state().lock().await.config.port_forwarding.is_empty());
   Ok(())
 }
 
+fn fixture(name: &str) -> String {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/onu").join(name);
+  std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("read {}: {}", path.display(), e))
+}
+
+/// compare `value` with `fixtures/onu/{name}.json`, run with `UPDATE_GOLDEN=1` to regenerate
+fn assert_golden<T: serde::Serialize>(name: &str, value: &T) {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/onu").join(format!("{}.json", name));
+  let actual = serde_json::to_string_pretty(value).unwrap() + "\n";
+  if std::env::var_os("UPDATE_GOLDEN").is_some() {
+    std::fs::write(&path, &actual).unwrap();
+    return;
+  }
+  let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("read {}: {}", path.display(), e));
+  assert_eq!(actual, expected, "golden mismatch: {}", path.display());
+}
+
+#[test]
+fn test_golden_wan_info() -> Result<()> {
+  for name in ["status_ethwan_if_t.f660_v5", "status_ethwan_if_t.f660_v6", "status_ethwan_if_t.pppoe_down"] {
+    let info = Context::parse_wan_info(&fixture(&format!("{}.gch", name)))?;
+    assert_golden(&format!("{}.wan_info", name), &info);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_golden_space0_tables() -> Result<()> {
+  for name in ["status_ethwan_if_t.f660_v5", "status_wanstatu_ipv6wansta_t.f660_v5"] {
+    let tables = parse_space0_tables(&fixture(&format!("{}.gch", name)))?;
+    let tables = tables.into_iter().map(|kv| kv.into_iter().collect::<std::collections::BTreeMap<_, _>>()).collect::<Vec<_>>();
+    assert_golden(&format!("{}.space0", name), &tables);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_golden_wan6_info() -> Result<()> {
+  let info = Context::parse_wan6_info(&fixture("status_wanstatu_ipv6wansta_t.f660_v5.gch"))?;
+  assert_golden("status_wanstatu_ipv6wansta_t.f660_v5.wan6_info", &info);
+  Ok(())
+}
+
+#[test]
+fn test_golden_lan_info() -> Result<()> {
+  for name in ["status_ethlan_dhcp_info_t.f660_v5", "status_ethlan_dhcp_info_t.empty", "status_ethlan_dhcp_info_t.utf8"] {
+    let info = Context::parse_lan_info(&fixture(&format!("{}.gch", name)))?;
+    assert_golden(&format!("{}.lan_info", name), &info);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_golden_forwarding_list() -> Result<()> {
+  for name in [
+    "app_virtual_conf_t.f660_v5", "app_virtual_conf_t.f7607", "app_virtual_conf_t.empty",
+    "app_virtual_conf_t.apply_succ", "app_virtual_conf_t.apply_error",
+  ] {
+    let resp = fixture(&format!("{}.gch", name));
+    assert_golden(&format!("{}.forwarding_list", name), &Context::parse_forwarding_list(&resp)?);
+    assert_golden(&format!("{}.api_result", name), &Request::parse_api_result(&resp));
+  }
+  Ok(())
+}
+
+#[test]
+fn test_golden_wanc_info() -> Result<()> {
+  for name in ["app_virtual_conf_t.f660_v5", "app_virtual_conf_t.f7607", "app_virtual_conf_t.empty"] {
+    let info = Context::parse_wanc_info(&fixture(&format!("{}.gch", name)))?;
+    assert_golden(&format!("{}.wanc_info", name), &info);
+  }
+  Ok(())
+}
+
+#[test]
+fn test_golden_session() {
+  for name in ["template", "status_ethwan_if_t.f660_v5", "app_virtual_conf_t.apply_succ"] {
+    let session = Request::parse_session(&fixture(&format!("{}.gch", name)));
+    assert_golden(&format!("{}.session", name), &session);
+  }
+}
+
+#[test]
+fn test_parse_error() {
+  let err = Context::parse_lan_info("<html></html>").unwrap_err();
+  assert!(matches!(&err, OnuError::Parse { page, field } if page == "status_ethlan_dhcp_info_t.gch" && field == "IF_INSTNUM"), "{:?}", err);
+  let resp = fixture("app_virtual_conf_t.f660_v5.gch").replace("Transfer_meaning('Protocol1','2');", "Transfer_meaning('Protocol1','9');");
+  let err = Context::parse_forwarding_list(&resp).unwrap_err();
+  assert!(matches!(&err, OnuError::Parse { field, .. } if field == "Protocol1"), "{:?}", err);
+  let err = Request::parse_api_result(&fixture("app_virtual_conf_t.apply_error.gch")).check().unwrap_err();
+  assert_eq!(err.to_string(), "router reported PortConflict (IF_ERRORPARAM: MinExtPort, IF_ERRORTYPE: -1)");
+  let err = Request::parse_api_result(&fixture("app_virtual_conf_t.f660_v5.gch")).check().unwrap_err();
+  assert_eq!(err.to_string(), "router reported no IF_ERRORSTR");
+}
+
+#[test]
+fn test_unescape_hex() {
+  assert_eq!(unescape_hex("192\\x2e168\\x2e1\\x2e1"), "192.168.1.1");
+  assert_eq!(unescape_hex("a\\x20\\x28b\\x29"), "a (b)");
+  assert_eq!(unescape_hex("bad\\xzz"), "bad\\xzz");
+  assert_eq!(unescape_hex("tail\\x2"), "tail\\x2");
+  assert_eq!(unescape_hex("\\xe5\\xae\\xb6\\x2d5G"), "家-5G");
+  assert_eq!(unescape_hex("caf\\xc3\\xa9 café"), "café café");
+  assert_eq!(unescape_hex("\\x+1"), "\\x+1");
+}
+
 }
@@ -0,0 +1,243 @@
+use std::{collections::HashSet, path::Path};
+
+use super::{sync::diff, Context, OnuError, PortForwardingAction, PortForwardingParam, PortForwardingSelector, Result};
+
+/// file format of `export_rules` and `parse_rules`, the columns are the serde names of `PortForwardingParam`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleFormat {
+  Csv, Json,
+}
+
+impl RuleFormat {
+  /// guess from the extension, json by default
+  pub fn from_path(path: &Path) -> Self {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("csv") => Self::Csv,
+      _ => Self::Json,
+    }
+  }
+}
+
+/// dump `list` without the rules of UPnP clients, which come back by themselves
+pub fn export_rules(list: &[PortForwardingParam], format: RuleFormat) -> Result<String> {
+  let list = list.iter().filter(|p| !p.is_upnp()).collect::<Vec<_>>();
+  let serialize_error = |reason: String| OnuError::Serialize { what: "rules", reason };
+  match format {
+    RuleFormat::Json => serde_json::to_string_pretty(&list).map_err(|e| serialize_error(e.to_string())),
+    RuleFormat::Csv => {
+      let mut writer = csv::Writer::from_writer(Vec::new());
+      for param in list {
+        writer.serialize(param).map_err(|e| serialize_error(e.to_string()))?;
+      }
+      let data = writer.into_inner().map_err(|e| e.into_error())?;
+      Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+  }
+}
+
+/// every row of `content`, a malformed row does not stop the others
+pub fn parse_rules(content: &str, format: RuleFormat) -> std::result::Result<Vec<std::result::Result<PortForwardingParam, String>>, String> {
+  match format {
+    RuleFormat::Json => {
+      let rows: Vec<serde_json::Value> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+      Ok(rows.into_iter().map(|row| serde_json::from_value(row).map_err(|e| e.to_string())).collect())
+    }
+    RuleFormat::Csv => {
+      let mut reader = csv::Reader::from_reader(content.as_bytes());
+      Ok(reader.deserialize().map(|row| row.map_err(|e| e.to_string())).collect())
+    }
+  }
+}
+
+/// `parse_rules` of the file at `path`, `OnuError::Load` if the file is not a list of rules at all
+pub fn load_rules<P: AsRef<Path>>(path: P, format: RuleFormat) -> Result<Vec<std::result::Result<PortForwardingParam, String>>> {
+  let path = path.as_ref();
+  let content = std::fs::read_to_string(path)?;
+  parse_rules(&content, format).map_err(|reason| OnuError::Load { path: path.display().to_string(), reason })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+  /// add new rules and overwrite the ones with the same name, keep the rest
+  Merge,
+  /// like `Merge`, and delete rules not in the file (except those of UPnP clients),
+  /// nothing is touched unless every row is valid against the list as it will be
+  Replace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportOutcome {
+  Added,
+  Updated,
+  Unchanged,
+  Failed(String),
+}
+
+impl std::fmt::Display for ImportOutcome {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ImportOutcome::Added => write!(f, "added"),
+      ImportOutcome::Updated => write!(f, "updated"),
+      ImportOutcome::Unchanged => write!(f, "unchanged"),
+      ImportOutcome::Failed(e) => write!(f, "failed: {}", e),
+    }
+  }
+}
+
+/// result of `Context::port_forwarding_import`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+  /// `(row, name, outcome)`, row starts from 1
+  pub rows: Vec<(usize, String, ImportOutcome)>,
+  /// deleted by `ImportMode::Replace`
+  pub removed: Vec<PortForwardingParam>,
+}
+
+impl ImportReport {
+  pub fn failed(&self) -> usize {
+    self.rows.iter().filter(|(_, _, o)| matches!(o, ImportOutcome::Failed(_))).count()
+  }
+}
+
+impl Context {
+  /// submit the rows of `parse_rules` one by one, errors are kept in the report per row
+  pub async fn port_forwarding_import(&mut self, rows: Vec<std::result::Result<PortForwardingParam, String>>, mode: ImportMode) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let names = rows.iter().filter_map(|r| r.as_ref().ok()).map(|p| p.name.clone()).collect::<HashSet<_>>();
+    if mode == ImportMode::Replace {
+      let (kept, removed) = self.port_forwarding_list().await?.into_iter()
+        .partition::<Vec<_>, _>(|p| p.is_upnp() || names.contains(&p.name));
+      let failures = self.check_replace(&rows, kept);
+      if !failures.is_empty() {
+        return Err(OnuError::InvalidRequest(format!("nothing is replaced, {}", failures.join(", "))));
+      }
+      for param in removed {
+        self.port_forwarding_remove(&PortForwardingSelector::Fingerprint(param.fingerprint())).await?;
+        report.removed.push(param);
+      }
+    }
+
+    let mut seen = HashSet::new();
+    for (i, row) in rows.into_iter().enumerate() {
+      let param = match row {
+        Ok(param) => param,
+        Err(e) => {
+          report.rows.push((i + 1, String::new(), ImportOutcome::Failed(e)));
+          continue;
+        }
+      };
+      let name = param.name.clone();
+      if !seen.insert(name.clone()) {
+        report.rows.push((i + 1, name, ImportOutcome::Failed("duplicated name".to_string())));
+        continue;
+      }
+      let list = self.port_forwarding_list().await?;
+      let result = match list.iter().find(|p| p.name == param.name) {
+        Some(current) if diff(current, &param).is_empty() => Ok(ImportOutcome::Unchanged),
+        Some(current) => self.port_forwarding_apply(&PortForwardingSelector::Fingerprint(current.fingerprint()), param).await
+          .map(|_| ImportOutcome::Updated),
+        None => self.port_forwarding(PortForwardingAction::New, param).await
+          .map(|_| ImportOutcome::Added),
+      };
+      let outcome = result.unwrap_or_else(|e| ImportOutcome::Failed(e.to_string()));
+      report.rows.push((i + 1, name, outcome));
+    }
+    Ok(report)
+  }
+
+  /// `row {n}: {reason}` of every row that would fail when submitted after `kept` is all that is left
+  fn check_replace(&self, rows: &[std::result::Result<PortForwardingParam, String>], mut list: Vec<PortForwardingParam>) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, row) in rows.iter().enumerate() {
+      let param = match row {
+        Ok(param) => param,
+        Err(e) => {
+          failures.push(format!("row {}: {}", i + 1, e));
+          continue;
+        }
+      };
+      if !seen.insert(&param.name) {
+        failures.push(format!("row {}: duplicated name", i + 1));
+        continue;
+      }
+      let index = list.iter().position(|p| p.name == param.name);
+      if let Some(validator) = &self.validator {
+        let issues = validator.check(param, index.map(|i| i as u32), &list);
+        if !issues.is_empty() {
+          failures.push(format!("row {}: {}", i + 1, OnuError::Validation(issues)));
+          continue;
+        }
+      }
+      match index {
+        Some(index) => list[index] = param.clone(),
+        None => list.push(param.clone()),
+      }
+    }
+    failures
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::{mock, PortForwardingHost, PortForwardingPort};
+
+fn rule(name: &str, port: u32) -> PortForwardingParam {
+  PortForwardingParam::new(name, "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.4".to_string()), PortForwardingPort::Simple(port))
+}
+
+#[test]
+fn test_export_parse() {
+  let upnp = PortForwardingParam { port_map_creator: Some("UPnP".to_string()), ..rule("upnp", 51413) };
+  let list = vec![rule("web", 8080).with_description("a, \"quoted\" one"), upnp, rule("ssh", 2222)];
+  for format in [RuleFormat::Csv, RuleFormat::Json] {
+    let content = export_rules(&list, format).unwrap();
+    let rows = parse_rules(&content, format).unwrap();
+    assert_eq!(rows, vec![Ok(list[0].clone()), Ok(list[2].clone())], "{:?}", format);
+  }
+  let csv = "enable,name,protocol\n1,web,0\n";
+  assert!(parse_rules(csv, RuleFormat::Csv).unwrap()[0].is_err());
+  assert!(parse_rules("{}", RuleFormat::Json).is_err());
+}
+
+#[tokio::test]
+async fn test_port_forwarding_import() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig {
+    port_forwarding: vec![rule("web", 8080), rule("old", 8443), rule("ssh", 2222)],
+    ..Default::default()
+  }).await?;
+
+  let rows = vec![
+    Ok(rule("web", 8080)),
+    Ok(rule("ssh", 2222).with_description("moved")),
+    Err("missing field `name`".to_string()),
+    Ok(rule("rdp", 8080)),
+    Ok(rule("game", 27015)),
+    Ok(rule("game", 27016)),
+  ];
+  let report = ctx.port_forwarding_import(rows.clone(), ImportMode::Merge).await?;
+  let outcomes = report.rows.iter().map(|(i, _, o)| (*i, o.to_string())).collect::<Vec<_>>();
+  assert_eq!(outcomes[..2], [(1, "unchanged".to_string()), (2, "updated".to_string())]);
+  assert!(outcomes[2].1.starts_with("failed: missing field"));
+  assert!(outcomes[3].1.starts_with("failed: invalid rule"), "{:?}", outcomes[3]);
+  assert_eq!(outcomes[4..], [(5, "added".to_string()), (6, "failed: duplicated name".to_string())]);
+  assert_eq!(report.failed(), 3);
+  let names = ctx.port_forwarding_list().await?.into_iter().map(|p| p.name).collect::<Vec<_>>();
+  assert_eq!(names, ["web", "old", "ssh", "game"]);
+
+  assert!(ctx.port_forwarding_import(rows, ImportMode::Replace).await.is_err());
+  // "ssh" is only valid once "old" is gone, "clash" collides with the kept "web"
+  let rows = vec![Ok(rule("web", 8080)), Ok(rule("ssh", 8443)), Ok(rule("clash", 8080))];
+  let err = ctx.port_forwarding_import(rows, ImportMode::Replace).await.unwrap_err();
+  assert!(err.to_string().contains("row 3: invalid rule"), "{}", err);
+  assert!(!err.to_string().contains("row 2"), "{}", err);
+  let names = ctx.port_forwarding_list().await?.into_iter().map(|p| p.name).collect::<Vec<_>>();
+  assert_eq!(names, ["web", "old", "ssh", "game"]);
+  let report = ctx.port_forwarding_import(vec![Ok(rule("web", 8080)), Ok(rule("game", 27015))], ImportMode::Replace).await?;
+  assert_eq!(report.removed.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["old", "ssh"]);
+  let names = ctx.port_forwarding_list().await?.into_iter().map(|p| p.name).collect::<Vec<_>>();
+  assert_eq!(names, ["web", "game"]);
+  Ok(())
+}
+}
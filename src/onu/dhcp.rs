@@ -0,0 +1,366 @@
+use std::net::Ipv4Addr;
+
+use super::{
+  parse_transfer_meaning, serde_str01_as_bool, serde_str_as_u32, serde_strnull_as_option, display_option,
+  target::same_mac, validate::{is_valid_mac, ValidationIssue},
+  Context, OnuError, PortForwardingHost, PortForwardingTarget, Result,
+};
+
+/// how long `Context::lan_dhcp_set` waits for the reply before the router moves to the new `lan_ip`
+const MOVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// one entry of `net_dhcp_static_t.gch`, the DHCP server always leases `ip` to `mac`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct DhcpBinding {
+  #[serde(rename = "Name")]
+  pub name: String,
+  /// xx:xx:xx:xx:xx:xx
+  #[serde(rename = "MACAddr")]
+  pub mac: String,
+  #[serde(rename = "IPAddr")]
+  pub ip: String,
+}
+
+impl DhcpBinding {
+  /// `key` is the mac, the ip or the name of the binding
+  pub fn matches(&self, key: &str) -> bool {
+    same_mac(&self.mac, key) || self.ip == key || self.name == key
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct DhcpBindingRequest {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(rename = "IF_INDEX")]
+  index: i32,
+  #[serde(flatten)]
+  binding: DhcpBinding,
+}
+
+/// parsing from `net_dhcp_dynamic_t.gch`, the LAN address of the router and what its DHCP server hands out
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct LanDhcpConfig {
+  /// the router itself, e.g. 192.168.1.1
+  #[serde(rename = "IPAddr")]
+  pub lan_ip: String,
+  #[serde(rename = "SubnetMask")]
+  pub subnet_mask: String,
+  #[serde(rename = "ServerEnable", with = "serde_str01_as_bool")]
+  pub server_enable: bool,
+  #[serde(rename = "MinAddress")]
+  pub pool_start: String,
+  #[serde(rename = "MaxAddress")]
+  pub pool_end: String,
+  /// seconds
+  #[serde(rename = "LeaseTime", with = "serde_str_as_u32")]
+  pub lease_time: u32,
+  /// the gateway handed out, usually `lan_ip`
+  #[serde(rename = "IPRouters")]
+  pub gateway: String,
+  #[serde(rename = "DNSServer1", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub dns1: Option<String>,
+  #[serde(rename = "DNSServer2", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub dns2: Option<String>,
+  #[serde(rename = "DNSServer3", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub dns3: Option<String>,
+}
+
+impl LanDhcpConfig {
+  /// replace the DNS servers handed out, at most 3 are kept
+  pub fn with_dns(mut self, dns: &[String]) -> Self {
+    let mut dns = dns.iter().cloned();
+    self.dns1 = dns.next();
+    self.dns2 = dns.next();
+    self.dns3 = dns.next();
+    self
+  }
+
+  /// every problem of the addresses, checked before any POST by `Context::lan_dhcp_set`
+  pub fn check(&self) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut parse = |field: &'static str, value: &str| match value.parse::<Ipv4Addr>() {
+      Ok(addr) => Some(u32::from(addr)),
+      Err(_) => {
+        issues.push(ValidationIssue::InvalidAddress { field, value: value.to_string() });
+        None
+      }
+    };
+    let lan_ip = parse("lan ip", &self.lan_ip);
+    let mask = parse("subnet mask", &self.subnet_mask);
+    let pool_start = parse("pool start", &self.pool_start);
+    let pool_end = parse("pool end", &self.pool_end);
+    let gateway = parse("gateway", &self.gateway);
+    for (field, dns) in [("dns1", &self.dns1), ("dns2", &self.dns2), ("dns3", &self.dns3)] {
+      if let Some(dns) = dns {
+        parse(field, dns);
+      }
+    }
+
+    let mask = mask.filter(|mask| {
+      let contiguous = mask.leading_ones() + mask.trailing_zeros() == 32 && *mask != 0;
+      if !contiguous {
+        issues.push(ValidationIssue::InvalidSubnetMask(self.subnet_mask.clone()));
+      }
+      contiguous
+    });
+    if let (Some(lan_ip), Some(mask)) = (lan_ip, mask) {
+      for (field, value, addr) in [
+        ("pool start", &self.pool_start, pool_start),
+        ("pool end", &self.pool_end, pool_end),
+        ("gateway", &self.gateway, gateway),
+      ] {
+        if addr.is_some_and(|addr| addr & mask != lan_ip & mask) {
+          issues.push(ValidationIssue::OutsideSubnet { field, value: value.clone() });
+        }
+      }
+    }
+    if let (Some(start), Some(end)) = (pool_start, pool_end) {
+      if start > end {
+        issues.push(ValidationIssue::InvalidAddress { field: "pool range", value: format!("{}-{}", self.pool_start, self.pool_end) });
+      } else if lan_ip.is_some_and(|ip| (start..=end).contains(&ip)) {
+        issues.push(ValidationIssue::AddressInPool(self.lan_ip.clone()));
+      }
+    }
+    issues
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LanDhcpRequest {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(flatten)]
+  config: LanDhcpConfig,
+}
+
+impl Context {
+  pub fn parse_lan_dhcp_config(resp: &str) -> Result<LanDhcpConfig> {
+    let page = "net_dhcp_dynamic_t.gch";
+    let field = |name: &str| parse_transfer_meaning(resp, name).ok_or_else(|| OnuError::parse(page, name));
+    let dns = |name: &str| parse_transfer_meaning(resp, name).filter(|d| !d.is_empty() && d != "NULL" && d != "0.0.0.0");
+    Ok(LanDhcpConfig {
+      lan_ip: field("IPAddr")?,
+      subnet_mask: field("SubnetMask")?,
+      server_enable: field("ServerEnable")? == "1",
+      pool_start: field("MinAddress")?,
+      pool_end: field("MaxAddress")?,
+      lease_time: field("LeaseTime")?.parse().map_err(|_| OnuError::parse(page, "LeaseTime"))?,
+      gateway: field("IPRouters")?,
+      dns1: dns("DNSServer1"),
+      dns2: dns("DNSServer2"),
+      dns3: dns("DNSServer3"),
+    })
+  }
+
+  pub async fn lan_dhcp_config(&mut self) -> Result<LanDhcpConfig> {
+    let (_, resp) = self.fetch("net_dhcp_dynamic_t.gch").await?;
+    Self::parse_lan_dhcp_config(&resp)
+  }
+
+  /// write the whole `config`, for a different `lan_ip` the router moves away in the middle of the request,
+  /// so `Context::base_url` follows it and logs in again there
+  pub async fn lan_dhcp_set(&mut self, config: &LanDhcpConfig) -> Result<LanDhcpConfig> {
+    let issues = config.check();
+    if !issues.is_empty() {
+      return Err(OnuError::Validation(issues));
+    }
+    let before = self.lan_dhcp_config().await?;
+    let request = LanDhcpRequest { action: "apply", config: config.clone() };
+    let current = if before.lan_ip == config.lan_ip {
+      let (err, resp) = self.post("net_dhcp_dynamic_t.gch").form(&request).send().await?;
+      err.check()?;
+      Self::parse_lan_dhcp_config(&resp)?
+    } else {
+      let sent = tokio::time::timeout(MOVE_TIMEOUT, self.post("net_dhcp_dynamic_t.gch").form(&request).send()).await;
+      match sent {
+        Ok(Ok((err, _))) => err.check()?,
+        // the reply is lost when the router leaves the old address
+        Ok(Err(e)) if e.is_session_expired() || matches!(e, OnuError::Transport(_)) => debug!("no reply from {}: {}", before.lan_ip, e),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => debug!("no reply from {} in {:?}", before.lan_ip, MOVE_TIMEOUT),
+      }
+      let mut url = reqwest::Url::parse(&self.base_url).map_err(|e| OnuError::InvalidRequest(format!("{}: {}", self.base_url, e)))?;
+      url.set_host(Some(&config.lan_ip)).map_err(|e| OnuError::InvalidRequest(format!("{}: {}", config.lan_ip, e)))?;
+      self.base_url = url.as_str().trim_end_matches('/').to_string();
+      info!("router moved from {} to {}", before.lan_ip, self.base_url);
+      self.session = None;
+      self.relogin().await?;
+      self.lan_dhcp_config().await?
+    };
+    if &current != config {
+      return Err(OnuError::Unconfirmed { action: "apply", rule: "lan dhcp config".to_string() });
+    }
+    Ok(current)
+  }
+
+  pub fn parse_dhcp_bindings(resp: &str) -> Result<Vec<DhcpBinding>> {
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse("net_dhcp_static_t.gch", "IF_INSTNUM"))?;
+    let list = (0..count).map(|i| DhcpBinding {
+      name: parse_transfer_meaning(resp, &format!("Name{}", i)).unwrap_or_default(),
+      mac: parse_transfer_meaning(resp, &format!("MACAddr{}", i)).unwrap_or_default(),
+      ip: parse_transfer_meaning(resp, &format!("IPAddr{}", i)).unwrap_or_default(),
+    }).collect();
+    Ok(list)
+  }
+
+  pub async fn dhcp_bindings(&mut self) -> Result<Vec<DhcpBinding>> {
+    let (_, resp) = self.fetch("net_dhcp_static_t.gch").await?;
+    Self::parse_dhcp_bindings(&resp)
+  }
+
+  async fn dhcp_binding_submit(&mut self, action: &'static str, index: i32, binding: DhcpBinding) -> Result<Vec<DhcpBinding>> {
+    let (err, resp) = self.post("net_dhcp_static_t.gch").form(&DhcpBindingRequest { action, index, binding }).send().await?;
+    err.check()?;
+    Self::parse_dhcp_bindings(&resp)
+  }
+
+  /// add `binding`, a mac or ip that is already bound elsewhere is refused
+  pub async fn dhcp_bind(&mut self, binding: DhcpBinding) -> Result<Vec<DhcpBinding>> {
+    let mut issues = Vec::new();
+    if !is_valid_mac(&binding.mac) {
+      issues.push(ValidationIssue::InvalidMac(binding.mac.clone()));
+    }
+    if binding.ip.parse::<Ipv4Addr>().is_err() {
+      issues.push(ValidationIssue::InvalidAddress { field: "dhcp binding", value: binding.ip.clone() });
+    }
+    if !issues.is_empty() {
+      return Err(OnuError::Validation(issues));
+    }
+    let list = self.dhcp_bindings().await?;
+    if let Some(current) = list.iter().find(|b| same_mac(&b.mac, &binding.mac) || b.ip == binding.ip) {
+      if same_mac(&current.mac, &binding.mac) && current.ip == binding.ip {
+        return Ok(list);
+      }
+      return Err(OnuError::InvalidRequest(format!("{} is already bound to {}, delete it first", current.mac, current.ip)));
+    }
+    let rule = format!("{} -> {}", binding.mac, binding.ip);
+    let list = self.dhcp_binding_submit("new", -1, binding.clone()).await?;
+    if !list.iter().any(|b| same_mac(&b.mac, &binding.mac) && b.ip == binding.ip) {
+      return Err(OnuError::Unconfirmed { action: "new", rule });
+    }
+    Ok(list)
+  }
+
+  /// delete the binding selected by `key`, see `DhcpBinding::matches`
+  pub async fn dhcp_unbind(&mut self, key: &str) -> Result<Vec<DhcpBinding>> {
+    let list = self.dhcp_bindings().await?;
+    let (index, binding) = list.iter().enumerate().find(|(_, b)| b.matches(key))
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find {} in dhcp bindings", key)))?;
+    let rule = format!("{} -> {}", binding.mac, binding.ip);
+    let list = self.dhcp_binding_submit("delete", index as i32, binding.clone()).await?;
+    if list.iter().any(|b| b.matches(key)) {
+      return Err(OnuError::Unconfirmed { action: "delete", rule });
+    }
+    Ok(list)
+  }
+
+  /// bind `target` to `ip`, or to its current lease in `Context::lan_info`
+  pub async fn dhcp_reserve(&mut self, target: &PortForwardingTarget, ip: Option<&str>) -> Result<Vec<DhcpBinding>> {
+    let mac = match self.resolve_target(target, true).await? {
+      PortForwardingHost::Mac(mac) => mac,
+      PortForwardingHost::Host(ip) => unreachable!("{} is resolved with pin_mac", ip),
+    };
+    let lan = self.lan_info().await?;
+    let lease = lan.iter().find(|l| same_mac(&l.mac, &mac));
+    let ip = match (ip, lease) {
+      (Some(ip), _) => ip.to_string(),
+      (None, Some(lease)) => lease.ip.clone(),
+      (None, None) => return Err(OnuError::InvalidRequest(format!("{} has no lease, give an ip to bind", target))),
+    };
+    let name = match (target, lease) {
+      (_, Some(lease)) if !lease.name.is_empty() => lease.name.clone(),
+      (PortForwardingTarget::Name(name), _) => name.clone(),
+      _ => mac.clone(),
+    };
+    self.dhcp_bind(DhcpBinding { name, mac, ip }).await
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[tokio::test]
+async fn test_dhcp_bindings() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  assert!(ctx.dhcp_bindings().await?.is_empty());
+
+  let nas = DhcpBinding { name: "nas".to_string(), mac: "aa:bb:cc:dd:ee:01".to_string(), ip: "192.168.1.4".to_string() };
+  let list = ctx.dhcp_reserve(&PortForwardingTarget::Name("NAS".to_string()), None).await?;
+  assert_eq!(list, vec![nas.clone()]);
+  assert_eq!(ctx.dhcp_bind(nas.clone()).await?, vec![nas.clone()]);
+  let phone = "AA-BB-CC-DD-EE-02".parse::<PortForwardingTarget>().unwrap();
+  let err = ctx.dhcp_reserve(&phone, Some("192.168.1.4")).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  let list = ctx.dhcp_reserve(&phone, Some("192.168.1.20")).await?;
+  assert_eq!(list[1], DhcpBinding { name: "phone".to_string(), mac: "aa:bb:cc:dd:ee:02".to_string(), ip: "192.168.1.20".to_string() });
+
+  assert_eq!(ctx.dhcp_unbind("aa:bb:cc:dd:ee:02").await?, [nas]);
+  assert!(ctx.dhcp_unbind("192.168.1.4").await?.is_empty());
+  assert!(ctx.dhcp_unbind("nas").await.is_err());
+  let err = ctx.dhcp_bind(DhcpBinding { name: "tv".to_string(), mac: "aa:bb".to_string(), ip: "192.168.1".to_string() }).await.unwrap_err();
+  assert!(matches!(&err, OnuError::Validation(issues) if issues.len() == 2), "{:?}", err);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_lan_dhcp_config() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let config = ctx.lan_dhcp_config().await?;
+  assert_eq!(config, mock::MockConfig::default().lan_dhcp);
+
+  let pihole = LanDhcpConfig { lease_time: 3600, ..config.clone() }.with_dns(&["192.168.1.2".to_string()]);
+  assert_eq!(ctx.lan_dhcp_set(&pihole).await?, pihole);
+  assert_eq!(ctx.lan_dhcp_config().await?.dns1.as_deref(), Some("192.168.1.2"));
+  assert_eq!(ctx.lan_dhcp_config().await?.dns2, None);
+
+  let bad = LanDhcpConfig {
+    subnet_mask: "255.255.0.255".to_string(),
+    pool_start: "192.168.1.200".to_string(),
+    pool_end: "192.168.1.100".to_string(),
+    ..config.clone()
+  }.with_dns(&["pihole".to_string()]);
+  assert_eq!(bad.check(), [
+    ValidationIssue::InvalidAddress { field: "dns1", value: "pihole".to_string() },
+    ValidationIssue::InvalidSubnetMask("255.255.0.255".to_string()),
+    ValidationIssue::InvalidAddress { field: "pool range", value: "192.168.1.200-192.168.1.100".to_string() },
+  ]);
+  let moved = LanDhcpConfig { pool_start: "192.168.1.1".to_string(), pool_end: "192.168.2.10".to_string(), ..config };
+  assert_eq!(moved.check(), [
+    ValidationIssue::OutsideSubnet { field: "pool end", value: "192.168.2.10".to_string() },
+    ValidationIssue::AddressInPool("192.168.1.1".to_string()),
+  ]);
+  assert!(matches!(ctx.lan_dhcp_set(&moved).await, Err(OnuError::Validation(_))));
+
+  let renumbered = LanDhcpConfig {
+    lan_ip: "10.0.0.1".to_string(),
+    pool_start: "10.0.0.100".to_string(),
+    pool_end: "10.0.0.199".to_string(),
+    gateway: "10.0.0.1".to_string(),
+    ..pihole.clone()
+  };
+  assert_eq!(renumbered.check(), []);
+
+  // the mock stays on 127.0.0.1, which is where the router moves to
+  let loopback = LanDhcpConfig {
+    lan_ip: "127.0.0.1".to_string(),
+    subnet_mask: "255.0.0.0".to_string(),
+    pool_start: "127.0.0.100".to_string(),
+    pool_end: "127.0.0.199".to_string(),
+    gateway: "127.0.0.1".to_string(),
+    ..pihole.clone()
+  };
+  let base_url = ctx.base_url.clone();
+  ctx.base_url = base_url.replace("127.0.0.1", "localhost");
+  assert_eq!(ctx.lan_dhcp_set(&loopback).await?, loopback);
+  assert_eq!(ctx.base_url, base_url);
+  assert_eq!(ctx.lan_dhcp_config().await?, loopback);
+  Ok(())
+}
+}
@@ -0,0 +1,140 @@
+use std::net::Ipv4Addr;
+
+use super::{
+  parse_transfer_meaning, serde_str01_as_bool, serde_strnull_as_option, display_option,
+  validate::{is_valid_mac, ValidationIssue}, Context, OnuError, PortForwardingHost, Result,
+};
+
+/// one entry of `app_dmz_conf_t.gch`, all unsolicited inbound traffic of `wan_interface` goes to the host
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct DmzParam {
+  #[serde(rename = "Enable", with = "serde_str01_as_bool")]
+  pub enable: bool,
+  /// "IGD.WD1.***", see `WancInfo::view_name`
+  #[serde(rename = "WANCViewName")]
+  pub wan_interface: String,
+  #[serde(rename = "InternalHost", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub local_addr: Option<String>,
+  #[serde(rename = "InternalMacHost", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub local_mac: Option<String>,
+  /// when enabled, `local_mac` is used, otherwise `local_addr` is used
+  #[serde(rename = "MacEnable", with = "serde_str01_as_bool")]
+  pub enable_local_mac: bool,
+}
+
+impl DmzParam {
+  pub fn host(&self) -> Option<PortForwardingHost> {
+    if self.enable_local_mac {
+      self.local_mac.clone().map(PortForwardingHost::Mac)
+    } else {
+      self.local_addr.clone().map(PortForwardingHost::Host)
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct DmzRequest {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(rename = "IF_INDEX")]
+  index: i32,
+  #[serde(flatten)]
+  params: DmzParam,
+}
+
+impl Context {
+  /// same `Transfer_meaning('{field}{i}', ...)` layout as `app_virtual_conf_t.gch`
+  pub fn parse_dmz_list(resp: &str) -> Result<Vec<DmzParam>> {
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse("app_dmz_conf_t.gch", "IF_INSTNUM"))?;
+    let list = (0..count).map(|i| DmzParam {
+      enable: parse_transfer_meaning(resp, &format!("Enable{}", i)).unwrap_or_default() == "1",
+      wan_interface: parse_transfer_meaning(resp, &format!("WANCViewName{}", i)).unwrap_or_default(),
+      local_addr: parse_transfer_meaning(resp, &format!("InternalHost{}", i)),
+      local_mac: parse_transfer_meaning(resp, &format!("InternalMacHost{}", i)),
+      enable_local_mac: parse_transfer_meaning(resp, &format!("MacEnable{}", i)).unwrap_or_default() == "1",
+    }).collect();
+    Ok(list)
+  }
+
+  pub async fn dmz_get(&mut self) -> Result<Vec<DmzParam>> {
+    let (_, resp) = self.fetch("app_dmz_conf_t.gch").await?;
+    Self::parse_dmz_list(&resp)
+  }
+
+  /// send everything of `wan_interface` to `host`, or turn the DMZ of `wan_interface` off with `None`
+  pub async fn dmz_set(&mut self, wan_interface: &str, host: Option<PortForwardingHost>) -> Result<Vec<DmzParam>> {
+    let list = self.dmz_get().await?;
+    let current = list.iter().position(|d| d.wan_interface == wan_interface);
+    let param = match (&host, current) {
+      (None, None) => return Ok(list),
+      (None, Some(i)) => DmzParam { enable: false, ..list[i].clone() },
+      (Some(host), _) => {
+        let valid = match host {
+          PortForwardingHost::Host(addr) => addr.parse::<Ipv4Addr>().is_ok(),
+          PortForwardingHost::Mac(mac) => is_valid_mac(mac),
+        };
+        if !valid {
+          let issue = match host {
+            PortForwardingHost::Host(addr) => ValidationIssue::InvalidAddress { field: "dmz host", value: addr.clone() },
+            PortForwardingHost::Mac(mac) => ValidationIssue::InvalidMac(mac.clone()),
+          };
+          return Err(OnuError::Validation(vec![issue]));
+        }
+        let base = current.map(|i| list[i].clone());
+        DmzParam {
+          enable: true,
+          wan_interface: wan_interface.to_string(),
+          local_addr: host.as_host().map(ToString::to_string).or(base.as_ref().and_then(|b| b.local_addr.clone())),
+          local_mac: host.as_mac().map(ToString::to_string).or(base.and_then(|b| b.local_mac)),
+          enable_local_mac: host.is_mac(),
+        }
+      }
+    };
+    let (action, index) = match current {
+      Some(i) => ("apply", i as i32),
+      None => ("new", -1),
+    };
+    let (err, resp) = self.post("app_dmz_conf_t.gch").form(&DmzRequest { action, index, params: param.clone() }).send().await?;
+    err.check()?;
+    let list = Self::parse_dmz_list(&resp)?;
+    let entry = list.iter().find(|d| d.wan_interface == wan_interface);
+    if entry.map(|d| (d.enable, d.host())) != Some((param.enable, param.host())) {
+      return Err(OnuError::Unconfirmed { action, rule: format!("dmz of {}", wan_interface) });
+    }
+    Ok(list)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[tokio::test]
+async fn test_dmz() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let wan = "IGD.WD1.WCD3.WCPPP1";
+  assert!(ctx.dmz_get().await?.is_empty());
+  assert!(ctx.dmz_set(wan, None).await?.is_empty());
+
+  let list = ctx.dmz_set(wan, Some(PortForwardingHost::Host("192.168.1.5".to_string()))).await?;
+  assert_eq!(list.len(), 1);
+  assert!(list[0].enable);
+  assert_eq!(list[0].host(), Some(PortForwardingHost::Host("192.168.1.5".to_string())));
+
+  let list = ctx.dmz_set(wan, Some(PortForwardingHost::Mac("aa:bb:cc:dd:ee:02".to_string()))).await?;
+  assert_eq!(list[0].host(), Some(PortForwardingHost::Mac("aa:bb:cc:dd:ee:02".to_string())));
+  assert_eq!(list[0].local_addr.as_deref(), Some("192.168.1.5"));
+
+  let list = ctx.dmz_set(wan, None).await?;
+  assert!(!list[0].enable);
+  assert_eq!(ctx.dmz_get().await?, list);
+
+  let err = ctx.dmz_set(wan, Some(PortForwardingHost::Host("console".to_string()))).await.unwrap_err();
+  assert!(matches!(err, OnuError::Validation(_)), "{:?}", err);
+  Ok(())
+}
+}
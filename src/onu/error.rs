@@ -0,0 +1,94 @@
+use super::{validate::ValidationIssue, ApiResult};
+
+pub type Result<T, E = OnuError> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OnuError {
+  /// `getObj("errmsg").innerHTML = "用户信息有误，请重新输入。";`
+  #[error("login failed: {errmsg}")]
+  BadCredentials { errmsg: String },
+  /// too many failed logins, the router refuses to login for a while
+  #[error("account locked: {errmsg}")]
+  AccountLocked { errmsg: String },
+  /// only one admin session is allowed, someone else (e.g. the web UI) is logged in
+  #[error("another user is logged in: {errmsg}")]
+  SessionConflict { errmsg: String },
+  /// the router answered with its login page
+  #[error("session expired while requesting {url}")]
+  SessionExpired { url: String },
+  /// `IF_ERRORSTR` is not `SUCC`
+  #[error("router reported {}", display_router_error(.error_str, .error_param, .error_type))]
+  Router {
+    error_str: String,
+    error_param: String,
+    error_type: String,
+  },
+  /// `field` is missing or malformed on `page`
+  #[error("failed to parse {field} on {page}")]
+  Parse { page: String, field: String },
+  /// the request is not valid for this method, e.g. `PortForwardingAction::New` for `Context::port_forwarding_delete`
+  #[error("invalid request: {0}")]
+  InvalidRequest(String),
+  /// the rule is refused by `Validator` before any POST
+  #[error("invalid rule: {}", display_issues(.0))]
+  Validation(Vec<ValidationIssue>),
+  /// the list returned by the router does not show `rule` being `action`ed,
+  /// someone else might have changed the list at the same time
+  #[error("router did not confirm {action} of {rule}")]
+  Unconfirmed { action: &'static str, rule: String },
+  /// a local file (e.g. the desired state of `sync`) is malformed
+  #[error("failed to load {path}: {reason}")]
+  Load { path: String, reason: String },
+  /// `what` cannot be written as json or csv, e.g. the rules of `export_rules`
+  #[error("failed to serialize {what}: {reason}")]
+  Serialize { what: &'static str, reason: String },
+  #[error("transport error: {0}")]
+  Transport(#[from] reqwest::Error),
+  #[error("io error: {0}")]
+  Io(#[from] std::io::Error),
+}
+
+fn display_router_error(error_str: &str, error_param: &str, error_type: &str) -> String {
+  if error_str.is_empty() {
+    return "no IF_ERRORSTR".to_string();
+  }
+  format!("{} (IF_ERRORPARAM: {}, IF_ERRORTYPE: {})", error_str, error_param, error_type)
+}
+
+fn display_issues(issues: &[ValidationIssue]) -> String {
+  issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+impl OnuError {
+  pub fn parse<P: ToString, F: ToString>(page: P, field: F) -> Self {
+    Self::Parse { page: page.to_string(), field: field.to_string() }
+  }
+
+  /// login again and retry might help
+  pub fn is_session_expired(&self) -> bool {
+    matches!(self, Self::SessionExpired { .. })
+  }
+
+  /// sort the `errmsg` of the login page into `SessionConflict`, `AccountLocked` or `BadCredentials`
+  pub fn from_login_errmsg(errmsg: String) -> Self {
+    const CONFLICT: &[&str] = &["已有用户登录", "已登录", "其他用户", "another user", "Another user", "already logged"];
+    const LOCKED: &[&str] = &["锁定", "次数过多", "稍后再试", "locked", "Locked", "too many", "Too many"];
+    if CONFLICT.iter().any(|pat| errmsg.contains(pat)) {
+      Self::SessionConflict { errmsg }
+    } else if LOCKED.iter().any(|pat| errmsg.contains(pat)) {
+      Self::AccountLocked { errmsg }
+    } else {
+      Self::BadCredentials { errmsg }
+    }
+  }
+}
+
+impl From<ApiResult> for OnuError {
+  fn from(value: ApiResult) -> Self {
+    Self::Router {
+      error_str: value.error_str,
+      error_param: value.error_param,
+      error_type: value.error_type,
+    }
+  }
+}
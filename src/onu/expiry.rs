@@ -0,0 +1,107 @@
+use std::time::SystemTime;
+
+use super::{sync::TaggedDescription, Context, PortForwardingParam, PortForwardingSelector, Result};
+
+/// `[expires=2024-03-01T10:00:00Z]` in `description`, the rule is deleted by `Context::port_forwarding_gc` after that
+pub const EXPIRES_TAG: &str = "expires";
+
+impl PortForwardingParam {
+  /// tag `description` with the time to delete this rule
+  pub fn with_expiry(mut self, at: SystemTime) -> Self {
+    let mut description = self.description.as_deref().map(TaggedDescription::parse).unwrap_or_default();
+    description.set_tag_value(EXPIRES_TAG, &humantime::format_rfc3339_seconds(at).to_string());
+    self.description = Some(description.to_string());
+    self
+  }
+
+  pub fn expires_at(&self) -> Option<SystemTime> {
+    let description = TaggedDescription::parse(self.description.as_deref()?);
+    humantime::parse_rfc3339(description.tag_value(EXPIRES_TAG)?).ok()
+  }
+
+  /// added by a UPnP client on the lan (`PortMappCreator` is "UPnP", or it has a lease),
+  /// the router removes it when the lease ends
+  pub fn is_upnp(&self) -> bool {
+    self.port_map_creator.as_deref().is_some_and(|c| c.eq_ignore_ascii_case("upnp"))
+      || self.lease_duration.as_deref().and_then(|l| l.parse::<u64>().ok()).is_some_and(|l| l > 0)
+  }
+}
+
+/// result of `Context::port_forwarding_gc`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+  /// expired and deleted
+  pub removed: Vec<PortForwardingParam>,
+  /// not expired yet
+  pub pending: Vec<PortForwardingParam>,
+  /// created by UPnP clients, left to the router
+  pub upnp: Vec<PortForwardingParam>,
+}
+
+impl Context {
+  /// delete every rule which `PortForwardingParam::expires_at` before `now`
+  pub async fn port_forwarding_gc(&mut self, now: SystemTime) -> Result<GcReport> {
+    let list = self.port_forwarding_list().await?;
+    let mut report = GcReport::default();
+    for param in list {
+      if param.is_upnp() {
+        report.upnp.push(param);
+        continue;
+      }
+      match param.expires_at() {
+        Some(at) if at <= now => {
+          info!("delete expired port forwarding {}", param.fingerprint());
+          self.port_forwarding_remove(&PortForwardingSelector::Fingerprint(param.fingerprint())).await?;
+          report.removed.push(param);
+        }
+        Some(_) => report.pending.push(param),
+        None => {}
+      }
+    }
+    Ok(report)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::onu::{mock, PortForwardingAction, PortForwardingHost, PortForwardingPort};
+
+#[test]
+fn test_expiry_tag() {
+  let at = humantime::parse_rfc3339("2024-03-01T10:00:00Z").unwrap();
+  let param = PortForwardingParam::new("game", "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.5".to_string()), PortForwardingPort::Simple(27015))
+    .with_description("friend [managed]")
+    .with_expiry(at - Duration::from_secs(60))
+    .with_expiry(at);
+  assert_eq!(param.description.as_deref(), Some("friend [managed] [expires=2024-03-01T10:00:00Z]"));
+  assert_eq!(param.expires_at(), Some(at));
+  assert!(!param.is_upnp());
+}
+
+#[tokio::test]
+async fn test_port_forwarding_gc() -> anyhow::Result<()> {
+  let rule = |name: &str, port: u32| PortForwardingParam::new(
+    name, "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.5".to_string()), PortForwardingPort::Simple(port));
+  let upnp = PortForwardingParam {
+    port_map_creator: Some("UPnP".to_string()),
+    lease_duration: Some("3600".to_string()),
+    ..rule("upnp", 51413)
+  };
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig { port_forwarding: vec![upnp], ..Default::default() }).await?;
+  let now = SystemTime::now();
+  ctx.port_forwarding(PortForwardingAction::New, rule("kept", 8080)).await?;
+  ctx.port_forwarding(PortForwardingAction::New, rule("expired", 27015).with_expiry(now - Duration::from_secs(1))).await?;
+  ctx.port_forwarding(PortForwardingAction::New, rule("later", 27016).with_expiry(now + Duration::from_secs(3600))).await?;
+
+  let report = ctx.port_forwarding_gc(now).await?;
+  let names = |list: &[PortForwardingParam]| list.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+  assert_eq!(names(&report.removed), ["expired"]);
+  assert_eq!(names(&report.pending), ["later"]);
+  assert_eq!(names(&report.upnp), ["upnp"]);
+  assert_eq!(names(&ctx.port_forwarding_list().await?), ["upnp", "kept", "later"]);
+  Ok(())
+}
+}
@@ -0,0 +1,242 @@
+use std::{path::Path, time::{Duration, SystemTime}};
+
+use rand::Rng;
+
+use super::{wlan::{find_ssid, WlanSsid}, Context, OnuError, Result};
+
+/// no 0/O, 1/l/I, easy to type from the screen
+const PASSPHRASE_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+pub fn generate_passphrase(len: usize) -> String {
+  let mut rng = rand::thread_rng();
+  (0..len).map(|_| PASSPHRASE_ALPHABET[rng.gen_range(0..PASSPHRASE_ALPHABET.len())] as char).collect()
+}
+
+/// what a visitor needs to join, returned by `Context::guest_wifi_on`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuestWifi {
+  /// index in `Context::wlan_ssids`
+  pub index: usize,
+  pub ssid: WlanSsid,
+  /// turn it off with `Context::guest_wifi_off` after this
+  pub expires_at: SystemTime,
+}
+
+/// `\`, `;`, `,`, `:` and `"` are escaped in the fields of a `WIFI:` code
+fn escape_wifi(s: &str) -> String {
+  s.chars().fold(String::new(), |mut acc, c| {
+    if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+      acc.push('\\');
+    }
+    acc.push(c);
+    acc
+  })
+}
+
+impl GuestWifi {
+  /// `WIFI:T:WPA;S:{ssid};P:{passphrase};;`, what phone cameras understand
+  pub fn qr_payload(&self) -> String {
+    let hidden = if self.ssid.hidden { "H:true;" } else { "" };
+    if self.ssid.is_open() {
+      return format!("WIFI:T:nopass;S:{};{};", escape_wifi(&self.ssid.ssid), hidden);
+    }
+    format!("WIFI:T:WPA;S:{};P:{};{};", escape_wifi(&self.ssid.ssid), escape_wifi(&self.ssid.passphrase), hidden)
+  }
+
+  /// `qr_payload` drawn with unicode half blocks, light on dark for terminals
+  pub fn qr_code(&self) -> Result<String> {
+    use qrcode::render::unicode::Dense1x2;
+    let code = qrcode::QrCode::new(self.qr_payload()).map_err(|e| OnuError::InvalidRequest(e.to_string()))?;
+    Ok(code.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build())
+  }
+}
+
+/// this mod is a serde helper that keeps a `SystemTime` as rfc3339, e.g. "2024-03-01T10:00:00Z"
+mod serde_rfc3339 {
+  use std::time::SystemTime;
+  use serde::{self, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(t: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_seconds(*t))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_rfc3339(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+/// a guest SSID to turn off at `expires_at`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GuestLease {
+  /// index in `Context::wlan_ssids`
+  pub index: usize,
+  /// the name when it was turned on, a renamed SSID is left alone
+  pub ssid: String,
+  #[serde(with = "serde_rfc3339")]
+  pub expires_at: SystemTime,
+}
+
+/// the state file of the guest SSIDs that are on, so `Context::guest_wifi_gc` (e.g. from cron)
+/// can turn them off after the process of `guest_wifi_on` is gone
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GuestLeases {
+  pub leases: Vec<GuestLease>,
+}
+
+impl GuestLeases {
+  /// empty if the file does not exist yet
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let content = match std::fs::read_to_string(path) {
+      Ok(content) => content,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+      Err(e) => return Err(e.into()),
+    };
+    serde_json::from_str(&content).map_err(|e| OnuError::Load { path: path.display().to_string(), reason: e.to_string() })
+  }
+
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    let content = serde_json::to_string_pretty(self)
+      .map_err(|e| OnuError::Serialize { what: "guest wifi leases", reason: e.to_string() })?;
+    Ok(std::fs::write(path, content)?)
+  }
+
+  /// replace the lease of the same SSID
+  pub fn insert(&mut self, guest: &GuestWifi) {
+    self.leases.retain(|l| l.index != guest.index);
+    self.leases.push(GuestLease { index: guest.index, ssid: guest.ssid.ssid.clone(), expires_at: guest.expires_at });
+  }
+
+  /// forget the SSIDs of `list` that are off already
+  pub fn retain_enabled(&mut self, list: &[WlanSsid]) {
+    self.leases.retain(|l| list.get(l.index).is_some_and(|s| s.enable && s.ssid == l.ssid));
+  }
+}
+
+/// the first SSID that is not the first one of its radio, the primary SSIDs are never used for guests
+pub fn guest_ssid(list: &[WlanSsid]) -> Option<usize> {
+  (0..list.len()).find(|&i| list[..i].iter().any(|s| s.radio == list[i].radio))
+}
+
+impl Context {
+  /// enable the SSID selected by `key` (see `find_ssid`, `guest_ssid` by default) with a fresh passphrase,
+  /// it stays on until `Context::guest_wifi_off`, keep it in `GuestLeases` for `Context::guest_wifi_gc`
+  pub async fn guest_wifi_on(&mut self, key: Option<&str>, ttl: Duration) -> Result<GuestWifi> {
+    let list = self.wlan_ssids().await?;
+    let index = match key {
+      Some(key) => find_ssid(&list, key).map(|(i, _)| i)
+        .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find ssid {}", key)))?,
+      None => guest_ssid(&list).ok_or_else(|| OnuError::InvalidRequest("no secondary ssid for guests".to_string()))?,
+    };
+    if !list[..index].iter().any(|s| s.radio == list[index].radio) {
+      return Err(OnuError::InvalidRequest(format!("{} is the primary ssid of its radio", list[index].ssid)));
+    }
+    let security = if list[index].is_open() { "WPA2-PSK".to_string() } else { list[index].security.clone() };
+    let ssid = WlanSsid { enable: true, security, passphrase: generate_passphrase(12), ..list[index].clone() };
+    let list = self.wlan_ssid_apply(&index.to_string(), ssid).await?;
+    Ok(GuestWifi { index, ssid: list[index].clone(), expires_at: SystemTime::now() + ttl })
+  }
+
+  /// disable the SSID selected by `key`, `guest_ssid` by default
+  pub async fn guest_wifi_off(&mut self, key: Option<&str>) -> Result<Vec<WlanSsid>> {
+    let list = self.wlan_ssids().await?;
+    let index = match key {
+      Some(key) => find_ssid(&list, key).map(|(i, _)| i),
+      None => guest_ssid(&list),
+    }.ok_or_else(|| OnuError::InvalidRequest(format!("cannot find ssid {}", key.unwrap_or("for guests"))))?;
+    if !list[index].enable {
+      return Ok(list);
+    }
+    self.wlan_ssid_apply(&index.to_string(), WlanSsid { enable: false, ..list[index].clone() }).await
+  }
+
+  /// turn off every SSID of `leases` that expires before `now`, and drop them from `leases`
+  pub async fn guest_wifi_gc(&mut self, leases: &mut GuestLeases, now: SystemTime) -> Result<Vec<GuestLease>> {
+    let mut removed = Vec::new();
+    for lease in leases.leases.iter().filter(|l| l.expires_at <= now) {
+      let list = self.wlan_ssids().await?;
+      if list.get(lease.index).is_some_and(|s| s.enable && s.ssid == lease.ssid) {
+        info!("turn off expired guest ssid {}", lease.ssid);
+        self.guest_wifi_off(Some(&lease.index.to_string())).await?;
+        removed.push(lease.clone());
+      }
+    }
+    leases.leases.retain(|l| l.expires_at > now);
+    Ok(removed)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[test]
+fn test_qr_payload() {
+  let passphrase = generate_passphrase(12);
+  assert_eq!(passphrase.len(), 12);
+  assert!(passphrase.bytes().all(|c| PASSPHRASE_ALPHABET.contains(&c)));
+  let ssid = WlanSsid {
+    enable: true, radio: 0, ssid: "guest;home".to_string(), hidden: false,
+    security: "WPA2-PSK".to_string(), passphrase: "a:b\\c".to_string(),
+  };
+  let guest = GuestWifi { index: 2, ssid, expires_at: SystemTime::UNIX_EPOCH };
+  assert_eq!(guest.qr_payload(), r"WIFI:T:WPA;S:guest\;home;P:a\:b\\c;;");
+  assert!(guest.qr_code().unwrap().contains('▀'));
+}
+
+#[tokio::test]
+async fn test_guest_wifi() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let before = ctx.wlan_ssids().await?;
+  assert_eq!(guest_ssid(&before), Some(2));
+
+  let guest = ctx.guest_wifi_on(None, Duration::from_secs(4 * 3600)).await?;
+  assert_eq!(guest.index, 2);
+  assert!(guest.ssid.enable);
+  assert_ne!(guest.ssid.passphrase, before[2].passphrase);
+  assert_eq!(guest.ssid.security, "WPA2-PSK");
+  assert!(guest.expires_at > SystemTime::now() + Duration::from_secs(3 * 3600));
+  let err = ctx.guest_wifi_on(Some(&before[1].ssid), Duration::from_secs(60)).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+
+  let list = ctx.guest_wifi_off(None).await?;
+  assert!(!list[2].enable);
+  assert_eq!(list[..2], before[..2]);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_guest_wifi_gc() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let path = std::env::temp_dir().join(format!("onu_test_guest_wifi_{}.json", std::process::id()));
+  let mut leases = GuestLeases::load(&path)?;
+  assert!(leases.leases.is_empty());
+  let guest = ctx.guest_wifi_on(None, Duration::from_secs(3600)).await?;
+  leases.insert(&guest);
+  leases.save(&path)?;
+
+  let mut leases = GuestLeases::load(&path)?;
+  std::fs::remove_file(&path)?;
+  assert_eq!(leases.leases, [GuestLease {
+    index: 2,
+    ssid: guest.ssid.ssid.clone(),
+    expires_at: humantime::parse_rfc3339(&humantime::format_rfc3339_seconds(guest.expires_at).to_string())?,
+  }]);
+  assert!(ctx.guest_wifi_gc(&mut leases, SystemTime::now()).await?.is_empty());
+  assert_eq!(leases.leases.len(), 1);
+  assert!(ctx.wlan_ssids().await?[2].enable);
+
+  let removed = ctx.guest_wifi_gc(&mut leases, guest.expires_at + Duration::from_secs(1)).await?;
+  assert_eq!(removed.len(), 1);
+  assert!(leases.leases.is_empty());
+  let list = ctx.wlan_ssids().await?;
+  assert!(!list[2].enable);
+
+  leases.insert(&guest);
+  leases.retain_enabled(&list);
+  assert!(leases.leases.is_empty());
+  Ok(())
+}
+}
@@ -0,0 +1,186 @@
+use sha2::{Digest, Sha256};
+
+use super::{unescape_hex, Context, OnuError, Result};
+
+/// how the landing page expects the password to be posted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LoginProtocol {
+  /// scrape `Frm_Logintoken` and POST the plaintext `Password`
+  Legacy,
+  /// F650/F660 firmwares with `Frm_Loginchecktoken`,
+  /// POST `Password = SHA256(password + UserRandomNum)`
+  SaltedSha256,
+  /// F7607 style `?_type=loginData&_tag=login_token` / `login_entry` exchange,
+  /// POST `Password = SHA256(password + login_token)` and get a JSON result
+  LoginEntry,
+}
+
+impl LoginProtocol {
+  /// detect the protocol from the landing page `GET /`
+  pub fn detect(landing: &str) -> Self {
+    if landing.contains("_tag=login_entry") {
+      Self::LoginEntry
+    } else if landing.contains("Frm_Loginchecktoken") || landing.contains("UserRandomNum") {
+      Self::SaltedSha256
+    } else {
+      Self::Legacy
+    }
+  }
+}
+
+pub fn sha256_hex(password: &str, salt: &str) -> String {
+  format!("{:x}", Sha256::digest(format!("{}{}", password, salt)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LoginAction {
+  Login,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LoginParam {
+  freshnum: String,
+  action: LoginAction,
+  /// this is come from line
+  /// `getObj("Frm_Logintoken").value = "4";`
+  #[serde(rename = "Frm_Logintoken")]
+  login_token: String,
+  #[serde(rename = "Username")]
+  username: String,
+  #[serde(rename = "Password")]
+  password: String,
+  /// the salt of `SaltedSha256`
+  #[serde(rename = "UserRandomNum", skip_serializing_if = "Option::is_none")]
+  random: Option<String>,
+  /// `getObj("Frm_Loginchecktoken").value = "4";`
+  #[serde(rename = "Frm_Loginchecktoken", skip_serializing_if = "Option::is_none")]
+  check_token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LoginEntryParam {
+  action: LoginAction,
+  #[serde(rename = "Username")]
+  username: String,
+  #[serde(rename = "Password")]
+  password: String,
+  /// `var _sessionTmpToken = "\x35\x36...";`
+  #[serde(rename = "_sessionTOKEN")]
+  session_token: String,
+}
+
+/// `{"sess_token":"...","login_need_refresh":true,"lockingTime":0,"loginErrMsg":""}`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LoginEntryResult {
+  #[serde(default)]
+  sess_token: String,
+  #[serde(rename = "lockingTime", default)]
+  locking_time: u32,
+  #[serde(rename = "loginErrMsg", default)]
+  login_err_msg: String,
+}
+
+/// parsing from `getObj("{id}").value = "{value}";`
+fn parse_obj_value(resp: &str, id: &str) -> Option<String> {
+  let value = resp
+    .split(&format!(r#"getObj("{}").value = "#, id)).nth(1)?
+    .split('"').nth(1)?;
+  Some(value.to_string())
+}
+
+impl Context {
+  pub(super) async fn login_form(&self, landing: &str, username: &str, password: &str, protocol: LoginProtocol) -> Result<()> {
+    let login_token = parse_obj_value(landing, "Frm_Logintoken").unwrap_or_else(|| "1".to_string());
+    debug!("login_token: {}", login_token);
+    let mut login_param = LoginParam {
+      freshnum: "".to_string(),
+      action: LoginAction::Login,
+      login_token,
+      username: username.to_string(),
+      password: password.to_string(),
+      random: None,
+      check_token: None,
+    };
+    if protocol == LoginProtocol::SaltedSha256 {
+      let random = parse_obj_value(landing, "UserRandomNum")
+        .unwrap_or_else(|| rand::random::<u32>().to_string());
+      login_param.password = sha256_hex(password, &random);
+      login_param.random = Some(random);
+      login_param.check_token = parse_obj_value(landing, "Frm_Loginchecktoken");
+    }
+
+    debug!("{:?}", login_param);
+    let resp = self._client.post(self.base_url()).form(&login_param).send().await?.text().await?;
+    if !resp.is_empty() && !resp.contains(r#"<iframe width="808px" height="67px" src="top.gch" name="topFrame" scrolling="no" frameborder="0" id="topFrame"></iframe>"#) {
+      // parse error message
+      // `getObj("errmsg").innerHTML = "用户信息有误，请重新输入。";`
+      debug!("content length: {}", resp.len());
+      let errmsg = resp
+        .replace("function SetDisabled()\n{\ngetObj(\"errmsg\").innerHTML", "function SetDisabled()\n{\ngetObj(\"errmsg\") .innerHTML")
+        .split(r#"getObj("errmsg").innerHTML = "#).nth(1).unwrap_or(r#""login might failed""#)
+        .split('"').nth(1).ok_or_else(|| OnuError::parse(self.base_url(), "errmsg"))?
+        .to_string();
+      error!("errmsg: {}", errmsg);
+      return Err(OnuError::from_login_errmsg(errmsg));
+    }
+    Ok(())
+  }
+
+  pub(super) async fn login_entry(&self, landing: &str, username: &str, password: &str) -> Result<()> {
+    let session_token = landing
+      .split("var _sessionTmpToken = ").nth(1)
+      .and_then(|s| s.split('"').nth(1)).map(unescape_hex)
+      .ok_or_else(|| OnuError::parse(self.base_url(), "_sessionTmpToken"))?;
+    // `<ajax_response_xml_root>12345678</ajax_response_xml_root>`
+    let resp = self._client.get(format!("{}/?_type=loginData&_tag=login_token", self.base_url()))
+      .send().await?.text().await?;
+    let login_token = resp
+      .split("<ajax_response_xml_root>").nth(1)
+      .and_then(|s| s.split("</ajax_response_xml_root>").next())
+      .ok_or_else(|| OnuError::parse("login_token", "ajax_response_xml_root"))?;
+    debug!("login_token: {}", login_token);
+    let param = LoginEntryParam {
+      action: LoginAction::Login,
+      username: username.to_string(),
+      password: sha256_hex(password, login_token),
+      session_token,
+    };
+    let resp = self._client.post(format!("{}/?_type=loginData&_tag=login_entry", self.base_url()))
+      .form(&param).send().await?.text().await?;
+    let result: LoginEntryResult = serde_json::from_str(&resp)
+      .map_err(|_| OnuError::parse("login_entry", "json"))?;
+    debug!("{:?}", result);
+    if result.login_err_msg.is_empty() {
+      return Ok(())
+    }
+    error!("errmsg: {}", result.login_err_msg);
+    if result.locking_time > 0 {
+      return Err(OnuError::AccountLocked { errmsg: result.login_err_msg });
+    }
+    Err(OnuError::from_login_errmsg(result.login_err_msg))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_detect() {
+    let legacy = r#"getObj("Frm_Logintoken").value = "4";"#;
+    let salted = r#"getObj("Frm_Logintoken").value = "4";
+getObj("Frm_Loginchecktoken").value = "1b2c";"#;
+    let entry = r#"var _sessionTmpToken = "\x31\x32";
+$.ajax({url: "?_type=loginData&_tag=login_entry"});"#;
+    assert_eq!(LoginProtocol::detect(legacy), LoginProtocol::Legacy);
+    assert_eq!(LoginProtocol::detect(salted), LoginProtocol::SaltedSha256);
+    assert_eq!(LoginProtocol::detect(entry), LoginProtocol::LoginEntry);
+    assert_eq!(parse_obj_value(salted, "Frm_Loginchecktoken").as_deref(), Some("1b2c"));
+  }
+
+  #[test]
+  fn test_sha256_hex() {
+    assert_eq!(sha256_hex("admin", "12345678"), "3c8872c094682f4c3fcdffdab80a8d351dbeee9b31a29e6ea092b51cf473e732");
+  }
+}
@@ -0,0 +1,753 @@
+//! A local stand-in for the ZTE ONU web UI, so `onu::Context` can be exercised
+//! without a router at 192.168.1.1.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use telegram_forcast56::onu::mock;
+//! let (mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+//! ctx.lan_info().await?;
+//! # Ok(()) }
+//! ```
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use axum::{extract::{Query, State}, response::Html, routing::get, Form, Router};
+use tokio::sync::Mutex;
+
+use super::{
+  login::sha256_hex, survey::Neighbor, upnp::UPnPStatus, DhcpBinding, DmzParam, LanDhcpConfig, LanInfo, LoginProtocol,
+  PortForwardingParam, Wan6Info, WanInfo, WancInfo, WanIpInfo, WlanClient, WlanRadio, WlanSsid,
+};
+
+pub const USERNAME: &str = "admin";
+pub const PASSWORD: &str = "admin";
+
+type ArcMockState = Arc<Mutex<MockState>>;
+
+/// the data served by the mock router
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+  pub username: String,
+  pub password: String,
+  /// lock the account after this many failed logins, `0` never locks
+  pub max_login_failures: u32,
+  pub login_protocol: LoginProtocol,
+  /// refuse to login while another session is alive, like the real router
+  pub single_session: bool,
+  pub wan: Vec<WanInfo>,
+  pub wan6: Vec<Wan6Info>,
+  pub lan: Vec<LanInfo>,
+  /// options of `Frm_WANCViewName` on `app_virtual_conf_t.gch`
+  pub wanc: Vec<WancInfo>,
+  pub port_forwarding: Vec<PortForwardingParam>,
+  pub dmz: Vec<DmzParam>,
+  pub upnp: UPnPStatus,
+  pub dhcp_bindings: Vec<DhcpBinding>,
+  pub lan_dhcp: LanDhcpConfig,
+  pub wlan_radios: Vec<WlanRadio>,
+  pub wlan_ssids: Vec<WlanSsid>,
+  pub wlan_clients: Vec<WlanClient>,
+  /// seen by a site survey, 2.4GHz or 5GHz by the channel
+  pub wlan_neighbors: Vec<Neighbor>,
+}
+
+impl Default for MockConfig {
+  fn default() -> Self {
+    Self {
+      username: USERNAME.to_string(),
+      password: PASSWORD.to_string(),
+      max_login_failures: 0,
+      login_protocol: LoginProtocol::Legacy,
+      single_session: false,
+      wan: vec![
+        WanInfo::DHCP {
+          name: "1_TR069_VOICE_R_VID_46".to_string(),
+          ip_info: WanIpInfo {
+            nat: "启用".to_string(),
+            ip: "10.46.0.12".to_string(),
+            dns1: "10.46.0.1".to_string(),
+            dns2: "0.0.0.0".to_string(),
+            dns3: "0.0.0.0".to_string(),
+            mac: "00:11:22:33:44:01".to_string(),
+            gateway: "10.46.0.1".to_string(),
+          },
+          status: "连接".to_string(),
+          lease_time: "85919秒".to_string(),
+        },
+        WanInfo::Bridge {
+          name: "2_Other_B_VID_85".to_string(),
+        },
+        WanInfo::PPPoE {
+          name: "3_INTERNET_R_VID_".to_string(),
+          ip_info: WanIpInfo {
+            nat: "启用".to_string(),
+            ip: "100.64.12.34".to_string(),
+            dns1: "202.96.128.86".to_string(),
+            dns2: "202.96.134.133".to_string(),
+            dns3: "0.0.0.0".to_string(),
+            mac: "00:11:22:33:44:03".to_string(),
+            gateway: "100.64.0.1".to_string(),
+          },
+          status: "连接".to_string(),
+          error_reason: String::new(),
+          uptime: "1156992秒".to_string(),
+        },
+      ],
+      wan6: vec![
+        Wan6Info {
+          name: "3_INTERNET_R_VID_".to_string(),
+          gua_assignment: "SLAAC".to_string(),
+          gua: "240e:1:2:3::1/64".to_string(),
+          dns_assignment: "DHCPv6".to_string(),
+          dns1: "240e:1::1".to_string(),
+          dns2: "240e:1::2".to_string(),
+          dns3: "::".to_string(),
+          wan_mac: "00:11:22:33:44:03".to_string(),
+          prefix_assignment: "DHCPv6".to_string(),
+          prefix: "240e:1:2:3::/60".to_string(),
+          gateway_assignment: "SLAAC".to_string(),
+          gateway: "fe80::1".to_string(),
+          status: "连接".to_string(),
+        },
+      ],
+      lan: vec![
+        LanInfo {
+          name: "nas".to_string(),
+          mac: "aa:bb:cc:dd:ee:01".to_string(),
+          ip: "192.168.1.4".to_string(),
+          lease_time: "58473".to_string(),
+          interface: "LAN4".to_string(),
+        },
+        LanInfo {
+          name: "phone".to_string(),
+          mac: "aa:bb:cc:dd:ee:02".to_string(),
+          ip: "192.168.1.5".to_string(),
+          lease_time: "3600".to_string(),
+          interface: "SSID1".to_string(),
+        },
+      ],
+      wanc: vec![
+        WancInfo {
+          name: "1_TR069_VOICE_R_VID_46".to_string(),
+          view_name: "IGD.WD1.WCD1.WCIP1".to_string(),
+          desc_name: String::new(),
+          ipmode: 1,
+        },
+        WancInfo {
+          name: "3_INTERNET_R_VID_".to_string(),
+          view_name: "IGD.WD1.WCD3.WCPPP1".to_string(),
+          desc_name: String::new(),
+          ipmode: 3,
+        },
+      ],
+      port_forwarding: vec![],
+      dmz: vec![],
+      upnp: UPnPStatus { enable: true, mappings: vec![] },
+      dhcp_bindings: vec![],
+      lan_dhcp: LanDhcpConfig {
+        lan_ip: "192.168.1.1".to_string(),
+        subnet_mask: "255.255.255.0".to_string(),
+        server_enable: true,
+        pool_start: "192.168.1.2".to_string(),
+        pool_end: "192.168.1.254".to_string(),
+        lease_time: 86400,
+        gateway: "192.168.1.1".to_string(),
+        dns1: Some("192.168.1.1".to_string()),
+        dns2: None,
+        dns3: None,
+      },
+      wlan_radios: vec![
+        WlanRadio { enable: true, band: "2.4GHz".to_string(), channel: 6, bandwidth: "20MHz".to_string(), tx_power: "100%".to_string() },
+        WlanRadio { enable: true, band: "5GHz".to_string(), channel: 36, bandwidth: "80MHz".to_string(), tx_power: "100%".to_string() },
+      ],
+      wlan_ssids: vec![
+        WlanSsid {
+          enable: true, radio: 0, ssid: "ChinaNet-home".to_string(), hidden: false,
+          security: "WPA/WPA2-PSK".to_string(), passphrase: "12345678".to_string(),
+        },
+        WlanSsid {
+          enable: true, radio: 1, ssid: "ChinaNet-home-5G".to_string(), hidden: false,
+          security: "WPA2-PSK".to_string(), passphrase: "12345678".to_string(),
+        },
+        WlanSsid {
+          enable: false, radio: 0, ssid: "ChinaNet-guest".to_string(), hidden: false,
+          security: "None".to_string(), passphrase: String::new(),
+        },
+      ],
+      wlan_clients: vec![
+        WlanClient {
+          mac: "AA:BB:CC:DD:EE:02".to_string(), ssid: "ChinaNet-home-5G".to_string(), band: None,
+          rssi: -48, tx_rate: 866, rx_rate: 650, connected_time: 5400, name: None, ip: None,
+        },
+        WlanClient {
+          mac: "aa:bb:cc:dd:ee:07".to_string(), ssid: "ChinaNet-home".to_string(), band: None,
+          rssi: -81, tx_rate: 6, rx_rate: 1, connected_time: 3665, name: None, ip: None,
+        },
+      ],
+      wlan_neighbors: vec![],
+    }
+  }
+}
+
+pub struct MockState {
+  pub config: MockConfig,
+  /// last `Frm_Logintoken` handed out by the login page
+  login_token: u32,
+  /// current `session_token`, `None` when nobody is logged in
+  session_token: Option<String>,
+  failed_logins: u32,
+  counter: u64,
+}
+
+impl MockState {
+  fn new(config: MockConfig) -> Self {
+    Self {
+      config,
+      login_token: 0,
+      session_token: None,
+      failed_logins: 0,
+      counter: 0,
+    }
+  }
+
+  fn next_session_token(&mut self) -> String {
+    self.counter += 1;
+    let token = format!("{:020}", self.counter.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407));
+    self.session_token = Some(token.clone());
+    token
+  }
+
+  /// drop the current session, as if it timed out on the router
+  pub fn expire_session(&mut self) {
+    self.session_token = None;
+  }
+
+  pub fn is_logged_in(&self) -> bool {
+    self.session_token.is_some()
+  }
+
+  fn login_page(&mut self, errmsg: Option<&str>) -> String {
+    self.login_token += 1;
+    if self.config.login_protocol == LoginProtocol::LoginEntry {
+      let token = self.login_token.to_string().bytes().map(|b| format!("\\x{:02x}", b)).collect::<String>();
+      return format!(r#"<html><head><title>F7607P</title>
+<script type="text/javascript">
+var _sessionTmpToken = "{token}";
+function login(){{ $.post("?_type=loginData&_tag=login_entry", getLoginData()); }}
+</script></head>
+<body><div id="loginArea"></div></body></html>"#);
+    }
+    let errmsg = errmsg.map(|msg| format!("getObj(\"errmsg\").innerHTML = \"{}\";\n", msg)).unwrap_or_default();
+    let salted = match self.config.login_protocol {
+      LoginProtocol::SaltedSha256 => format!(
+        "getObj(\"Frm_Loginchecktoken\").value = \"chk{}\";\ngetObj(\"UserRandomNum\").value = \"{}\";\n",
+        self.login_token, self.login_random()),
+      _ => String::new(),
+    };
+    format!(r#"<html><head><title>F660</title>
+<script language="javascript">
+function SetDisabled()
+{{
+getObj("errmsg").innerHTML = "";
+}}
+</script></head>
+<body>
+<form name="fLogin" method="post" action="">
+<input type="hidden" name="Frm_Logintoken" id="Frm_Logintoken" value="">
+<div id="errmsg"></div>
+</form>
+<script language="javascript">
+getObj("Frm_Logintoken").value = "{token}";
+{salted}{errmsg}</script>
+</body></html>"#, token = self.login_token)
+  }
+
+  fn login_random(&self) -> String {
+    format!("{}", 10000000 + self.login_token * 7919)
+  }
+
+  /// `salt` is `None` for `LoginProtocol::Legacy`, otherwise password is `SHA256(password + salt)`,
+  /// returns the errmsg on failure
+  fn check_credentials(&mut self, username: &str, password: &str, salt: Option<&str>) -> Result<(), &'static str> {
+    let max_failures = self.config.max_login_failures;
+    if max_failures > 0 && self.failed_logins >= max_failures {
+      return Err("登录失败次数过多，请1分钟后再试。");
+    }
+    let expected = match salt {
+      Some(salt) => sha256_hex(&self.config.password, salt),
+      None => self.config.password.clone(),
+    };
+    if username != self.config.username || password != expected {
+      self.failed_logins += 1;
+      return Err("用户信息有误，请重新输入。");
+    }
+    self.failed_logins = 0;
+    if self.config.single_session && self.session_token.is_some() {
+      return Err("当前已有用户登录，请稍后再试。");
+    }
+    self.next_session_token();
+    Ok(())
+  }
+
+  fn login(&mut self, form: &HashMap<String, String>) -> String {
+    let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+    if field("logout") == "1" {
+      if self.session_token.as_ref() == form.get("_SESSION_TOKEN") {
+        self.expire_session();
+      }
+      return self.login_page(None);
+    }
+    if field("action") != "login" || field("Frm_Logintoken") != self.login_token.to_string() {
+      return self.login_page(None);
+    }
+    let salt = match self.config.login_protocol {
+      LoginProtocol::Legacy => None,
+      LoginProtocol::SaltedSha256 if field("Frm_Loginchecktoken") == format!("chk{}", self.login_token) => Some(field("UserRandomNum")),
+      _ => return self.login_page(None),
+    };
+    if let Err(errmsg) = self.check_credentials(&field("Username"), &field("Password"), salt.as_deref()) {
+      return self.login_page(Some(errmsg));
+    }
+    r#"<html><body>
+<iframe width="808px" height="67px" src="top.gch" name="topFrame" scrolling="no" frameborder="0" id="topFrame"></iframe>
+</body></html>"#.to_string()
+  }
+
+  fn login_entry(&mut self, form: &HashMap<String, String>) -> String {
+    let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+    if field("_sessionTOKEN") != self.login_token.to_string() {
+      return serde_json::json!({ "loginErrMsg": "会话已过期", "lockingTime": 0 }).to_string();
+    }
+    let salt = self.login_random();
+    let result = self.check_credentials(&field("Username"), &field("Password"), Some(&salt));
+    match result {
+      Ok(()) => serde_json::json!({
+        "sess_token": self.session_token, "login_need_refresh": true, "lockingTime": 0, "loginErrMsg": "",
+      }),
+      Err(errmsg) => serde_json::json!({
+        "login_need_refresh": false,
+        "lockingTime": if self.failed_logins >= self.config.max_login_failures && self.config.max_login_failures > 0 { 60 } else { 0 },
+        "loginErrMsg": errmsg,
+      }),
+    }.to_string()
+  }
+
+  /// wrap `body` with the `session_token` and `getURL` script every page carries
+  fn page(&mut self, body: &str) -> String {
+    let session_token = self.next_session_token();
+    format!(r#"<html><head>
+<script type="text/javascript">
+var session_token = "{session_token}";
+function getURL(){{var ret = "getpage.gch?pid=1002&nextpage=";return ret;}}
+</script></head>
+<body>
+{body}
+</body></html>"#)
+  }
+
+  fn get_page(&mut self, page: &str) -> Option<String> {
+    let body = match page {
+      "status_ethwan_if_t.gch" => self.config.wan.iter().map(wan_table).collect(),
+      "status_wanstatu_ipv6wansta_t.gch" => self.config.wan6.iter().map(wan6_table).collect(),
+      "status_ethlan_dhcp_info_t.gch" => lan_script(&self.config.lan),
+      "app_virtual_conf_t.gch" => self.virtual_conf(None),
+      "app_dmz_conf_t.gch" => self.dmz_conf(None),
+      "app_upnp_conf_t.gch" => self.upnp_conf(None),
+      "net_dhcp_static_t.gch" => self.dhcp_static(None),
+      "net_dhcp_dynamic_t.gch" => self.dhcp_dynamic(None),
+      "net_wlanm_conf1_t.gch" => instances(&self.config.wlan_radios, None),
+      "net_wlanm_essid1_t.gch" => instances(&self.config.wlan_ssids, None),
+      "status_wlanm_assoc_t.gch" => instances(&self.config.wlan_clients, None),
+      _ => return None,
+    };
+    Some(self.page(&body))
+  }
+
+  fn post_page(&mut self, page: &str, form: &HashMap<String, String>) -> Option<String> {
+    let body = match page {
+      "app_virtual_conf_t.gch" => {
+        let result = self.virtual_conf_action(form);
+        self.virtual_conf(Some(result))
+      }
+      "app_dmz_conf_t.gch" => {
+        let result = self.dmz_conf_action(form);
+        self.dmz_conf(Some(result))
+      }
+      "app_upnp_conf_t.gch" => {
+        let result = self.upnp_conf_action(form);
+        self.upnp_conf(Some(result))
+      }
+      "net_dhcp_static_t.gch" => {
+        let result = self.dhcp_static_action(form);
+        self.dhcp_static(Some(result))
+      }
+      "net_dhcp_dynamic_t.gch" => {
+        let result = self.dhcp_dynamic_action(form);
+        self.dhcp_dynamic(Some(result))
+      }
+      "net_wlanm_conf1_t.gch" => {
+        let result = apply_instance(&mut self.config.wlan_radios, form);
+        instances(&self.config.wlan_radios, Some(result))
+      }
+      "net_wlanm_scan_t.gch" => {
+        let radio = form.get("IF_INDEX").and_then(|i| i.parse::<usize>().ok()).and_then(|i| self.config.wlan_radios.get(i));
+        match radio {
+          Some(radio) if form.get("IF_ACTION").map(String::as_str) == Some("scan") => {
+            let seen = self.config.wlan_neighbors.iter().filter(|n| (n.channel > 14) == radio.is_5ghz()).cloned().collect::<Vec<_>>();
+            instances(&seen, Some("SUCC"))
+          }
+          _ => instances::<Neighbor>(&[], Some("ParamError")),
+        }
+      }
+      "net_wlanm_essid1_t.gch" => {
+        let result = apply_instance(&mut self.config.wlan_ssids, form);
+        instances(&self.config.wlan_ssids, Some(result))
+      }
+      _ => return None,
+    };
+    Some(self.page(&body))
+  }
+
+  fn virtual_conf_action(&mut self, form: &HashMap<String, String>) -> &'static str {
+    let index = form.get("IF_INDEX").and_then(|i| i.parse::<usize>().ok());
+    let param = serde_json::to_value(form).and_then(serde_json::from_value::<PortForwardingParam>);
+    let list = &mut self.config.port_forwarding;
+    match (form.get("IF_ACTION").map(String::as_str), index, param) {
+      (Some("new"), _, Ok(param)) => list.push(param),
+      (Some("apply"), Some(i), Ok(param)) if i < list.len() => list[i] = param,
+      (Some("delete"), Some(i), _) if i < list.len() => { list.remove(i); },
+      _ => return "ParamError",
+    }
+    "SUCC"
+  }
+
+  fn virtual_conf(&self, result: Option<&str>) -> String {
+    let options = self.config.wanc.iter().map(|wanc| format!(
+      "<option value=\"{}\" ipmode=\"{}\" >{}</option>\n",
+      wanc.view_name, wanc.ipmode, html_entities(&wanc.name),
+    )).collect::<String>();
+    let mut script = result_script(result);
+    script += &transfer_meaning("IF_INSTNUM", &self.config.port_forwarding.len().to_string());
+    for (i, param) in self.config.port_forwarding.iter().enumerate() {
+      let value = serde_json::to_value(param).expect("PortForwardingParam is serializable");
+      for (field, key) in [
+        ("enable", "Enable"), ("name", "Name"), ("protocol", "Protocol"),
+        ("WANCViewName", "WANCViewName"), ("MinRemoteHost", "MinRemoteHost"), ("MaxRemoteHost", "MaxRemoteHost"),
+        ("MinExtPort", "MinExtPort"), ("MaxExtPort", "MaxExtPort"),
+        ("InternalHost", "InternalHost"), ("InternalMacHost", "InternalMacHost"), ("MacEnable", "MacEnable"),
+        ("MinIntPort", "MinIntPort"), ("MaxIntPort", "MaxIntPort"),
+        ("description", "Description"), ("PortMappCreator", "PortMappCreator"), ("lease_duration", "LeaseDuration"),
+      ] {
+        script += &transfer_meaning(&format!("{}{}", key, i), value[field].as_str().unwrap_or_default());
+      }
+    }
+    format!(r#"<select name="Frm_WANCViewName" id="Frm_WANCViewName"  size="1" class="list_13">
+{options}</select>
+<script language="javascript">
+{script}</script>"#)
+  }
+
+  fn dmz_conf_action(&mut self, form: &HashMap<String, String>) -> &'static str {
+    let index = form.get("IF_INDEX").and_then(|i| i.parse::<usize>().ok());
+    let param = serde_json::to_value(form).and_then(serde_json::from_value::<DmzParam>);
+    let list = &mut self.config.dmz;
+    match (form.get("IF_ACTION").map(String::as_str), index, param) {
+      (Some("new"), _, Ok(param)) => list.push(param),
+      (Some("apply"), Some(i), Ok(param)) if i < list.len() => list[i] = param,
+      _ => return "ParamError",
+    }
+    "SUCC"
+  }
+
+  fn dmz_conf(&self, result: Option<&str>) -> String {
+    let mut script = result_script(result);
+    script += &transfer_meaning("IF_INSTNUM", &self.config.dmz.len().to_string());
+    for (i, param) in self.config.dmz.iter().enumerate() {
+      let value = serde_json::to_value(param).expect("DmzParam is serializable");
+      for key in ["Enable", "WANCViewName", "InternalHost", "InternalMacHost", "MacEnable"] {
+        script += &transfer_meaning(&format!("{}{}", key, i), value[key].as_str().unwrap_or_default());
+      }
+    }
+    format!("<script language=\"javascript\">\n{script}</script>")
+  }
+  fn dhcp_static_action(&mut self, form: &HashMap<String, String>) -> &'static str {
+    let index = form.get("IF_INDEX").and_then(|i| i.parse::<usize>().ok());
+    let binding = serde_json::to_value(form).and_then(serde_json::from_value::<DhcpBinding>);
+    let list = &mut self.config.dhcp_bindings;
+    match (form.get("IF_ACTION").map(String::as_str), index, binding) {
+      (Some("new"), _, Ok(binding)) => list.push(binding),
+      (Some("delete"), Some(i), _) if i < list.len() => { list.remove(i); }
+      _ => return "ParamError",
+    }
+    "SUCC"
+  }
+
+  fn dhcp_static(&self, result: Option<&str>) -> String {
+    let mut script = result_script(result);
+    script += &transfer_meaning("IF_INSTNUM", &self.config.dhcp_bindings.len().to_string());
+    for (i, binding) in self.config.dhcp_bindings.iter().enumerate() {
+      script += &transfer_meaning(&format!("Name{}", i), &binding.name);
+      script += &transfer_meaning(&format!("MACAddr{}", i), &binding.mac);
+      script += &transfer_meaning(&format!("IPAddr{}", i), &binding.ip);
+    }
+    format!("<script language=\"javascript\">\n{script}</script>")
+  }
+
+  fn dhcp_dynamic_action(&mut self, form: &HashMap<String, String>) -> &'static str {
+    let config = serde_json::to_value(form).and_then(serde_json::from_value::<LanDhcpConfig>);
+    match (form.get("IF_ACTION").map(String::as_str), config) {
+      (Some("apply"), Ok(config)) => self.config.lan_dhcp = config,
+      _ => return "ParamError",
+    }
+    "SUCC"
+  }
+
+  fn dhcp_dynamic(&self, result: Option<&str>) -> String {
+    let mut script = result_script(result);
+    let value = serde_json::to_value(&self.config.lan_dhcp).expect("LanDhcpConfig is serializable");
+    for (key, value) in value.as_object().expect("LanDhcpConfig is a struct") {
+      script += &transfer_meaning(key, value.as_str().unwrap_or_default());
+    }
+    format!("<script language=\"javascript\">\n{script}</script>")
+  }
+
+  fn upnp_conf_action(&mut self, form: &HashMap<String, String>) -> &'static str {
+    let status = &mut self.config.upnp;
+    match (form.get("IF_ACTION").map(String::as_str), form.get("Enable").map(String::as_str)) {
+      (Some("apply"), Some("1")) => status.enable = true,
+      (Some("apply"), Some("0")) => {
+        status.enable = false;
+        status.mappings.clear();
+      }
+      _ => return "ParamError",
+    }
+    "SUCC"
+  }
+
+  fn upnp_conf(&self, result: Option<&str>) -> String {
+    let mut script = result_script(result);
+    script += &transfer_meaning("Enable", if self.config.upnp.enable { "1" } else { "0" });
+    script += &transfer_meaning("IF_INSTNUM", &self.config.upnp.mappings.len().to_string());
+    for (i, mapping) in self.config.upnp.mappings.iter().enumerate() {
+      let value = serde_json::to_value(mapping).expect("UPnPMapping is serializable");
+      for key in ["Enable", "Protocol", "RemoteHost", "ExtPort", "InternalClient", "IntPort", "Description", "LeaseDuration"] {
+        script += &transfer_meaning(&format!("{}{}", key, i), value[key].as_str().unwrap_or_default());
+      }
+    }
+    format!("<script language=\"javascript\">\n{script}</script>")
+  }
+
+
+}
+
+/// `IF_INSTNUM` and `Transfer_meaning('{field}{i}', ...)` of every serde field of `list`
+fn instances<T: serde::Serialize>(list: &[T], result: Option<&str>) -> String {
+  let mut script = result_script(result);
+  script += &transfer_meaning("IF_INSTNUM", &list.len().to_string());
+  for (i, item) in list.iter().enumerate() {
+    let value = serde_json::to_value(item).expect("mock data is serializable");
+    for (key, value) in value.as_object().expect("mock data is a struct") {
+      script += &transfer_meaning(&format!("{}{}", key, i), value.as_str().unwrap_or_default());
+    }
+  }
+  format!("<script language=\"javascript\">\n{script}</script>")
+}
+
+/// `IF_ACTION=apply` of the entry at `IF_INDEX`
+fn apply_instance<T: serde::de::DeserializeOwned>(list: &mut [T], form: &HashMap<String, String>) -> &'static str {
+  let index = form.get("IF_INDEX").and_then(|i| i.parse::<usize>().ok());
+  let item = serde_json::to_value(form).and_then(serde_json::from_value::<T>);
+  match (form.get("IF_ACTION").map(String::as_str), index, item) {
+    (Some("apply"), Some(i), Ok(item)) if i < list.len() => list[i] = item,
+    _ => return "ParamError",
+  }
+  "SUCC"
+}
+
+fn result_script(result: Option<&str>) -> String {
+  let Some(result) = result else { return String::new() };
+  let mut script = transfer_meaning("IF_ERRORSTR", result);
+  script += &transfer_meaning("IF_ERRORPARAM", if result == "SUCC" { "SUCC" } else { "IF_INDEX" });
+  script += &transfer_meaning("IF_ERRORTYPE", if result == "SUCC" { "SUCC" } else { "-1" });
+  script
+}
+
+/// the real router emits `&#NN;` for every character inside `<option>`
+fn html_entities(s: &str) -> String {
+  s.chars().map(|c| format!("&#{};", c as u32)).collect()
+}
+
+/// the real router hex-escapes punctuation and the utf-8 bytes of non-ascii inside `Transfer_meaning`
+fn transfer_meaning(field: &str, value: &str) -> String {
+  let value = value.chars().map(|c| match c {
+    '-' | '.' | ':' | '_' => format!("\\x{:02x}", c as u32),
+    c if !c.is_ascii() => c.to_string().bytes().map(|b| format!("\\x{:02x}", b)).collect(),
+    _ => c.to_string(),
+  }).collect::<String>();
+  format!("Transfer_meaning('{}','{}');\n", field, value)
+}
+
+fn space0_table(rows: &[(&str, &str)]) -> String {
+  let rows = rows.iter().map(|(k, v)| format!(
+    "<tr class=\"white_1\">\n<td class=\"tdleft_1\">{}</td>\n<td class=\"tdright\">{}</td>\n</tr>\n", k, v,
+  )).collect::<String>();
+  format!(r##"<div class="space_0">
+<table id="TestContent" class="infor" width="410" border="0" cellpadding="0" cellspacing="1" bgcolor="#979797">
+{rows}</table>
+</div>
+"##)
+}
+
+fn wan_table(wan: &WanInfo) -> String {
+  let ip_rows = |ip: &WanIpInfo| vec![
+    ("NAT", ip.nat.clone()), ("IP", ip.ip.clone()),
+    ("DNS1", ip.dns1.clone()), ("DNS2", ip.dns2.clone()), ("DNS3", ip.dns3.clone()),
+    ("网关", ip.gateway.clone()), ("WAN MAC", ip.mac.clone()),
+  ];
+  let rows = match wan {
+    WanInfo::PPPoE { name, ip_info, status, error_reason, uptime } => {
+      let mut rows = vec![("连接名称", name.clone()), ("模式", "PPPoE".to_string())];
+      rows.extend(ip_rows(ip_info));
+      rows.extend([("连接状态", status.clone()), ("断开原因", error_reason.clone()), ("在线时长", uptime.clone())]);
+      rows
+    },
+    WanInfo::DHCP { name, ip_info, status, lease_time } => {
+      let mut rows = vec![("连接名称", name.clone()), ("模式", "DHCP".to_string())];
+      rows.extend(ip_rows(ip_info));
+      rows.extend([("连接状态", status.clone()), ("剩余租期", lease_time.clone())]);
+      rows
+    },
+    WanInfo::Bridge { name } => vec![("连接名称", name.clone()), ("模式", "桥接".to_string())],
+  };
+  space0_table(&rows.iter().map(|(k, v)| (*k, v.as_str())).collect::<Vec<_>>())
+}
+
+fn wan6_table(wan6: &Wan6Info) -> String {
+  space0_table(&[
+    ("PPP", &wan6.name),
+    ("GUA获取方式", &wan6.gua_assignment), ("GUA", &wan6.gua),
+    ("DNS获取方式", &wan6.dns_assignment), ("DNS1", &wan6.dns1), ("DNS2", &wan6.dns2), ("DNS3", &wan6.dns3),
+    ("WAN MAC", &wan6.wan_mac),
+    ("前缀获取方式", &wan6.prefix_assignment), ("获取前缀", &wan6.prefix),
+    ("网关获取方式", &wan6.gateway_assignment), ("网关", &wan6.gateway),
+    ("连接状态", &wan6.status),
+  ])
+}
+
+fn lan_script(lan: &[LanInfo]) -> String {
+  let mut script = transfer_meaning("IF_INSTNUM", &lan.len().to_string());
+  for (i, info) in lan.iter().enumerate() {
+    script += &transfer_meaning(&format!("HostName{}", i), &info.name);
+    script += &transfer_meaning(&format!("MACAddr{}", i), &info.mac);
+    script += &transfer_meaning(&format!("IPAddr{}", i), &info.ip);
+    script += &transfer_meaning(&format!("ExpiredTime{}", i), &info.lease_time);
+    script += &transfer_meaning(&format!("PhyPortName{}", i), &info.interface);
+  }
+  format!("<script language=\"javascript\">\n{}</script>", script)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PageQuery {
+  #[serde(default)]
+  nextpage: String,
+}
+
+/// `?_type=loginData&_tag=login_token` of `LoginProtocol::LoginEntry`
+#[derive(Debug, serde::Deserialize)]
+struct LoginQuery {
+  #[serde(default)]
+  _tag: String,
+}
+
+async fn login_page(State(state): State<ArcMockState>, Query(query): Query<LoginQuery>) -> Html<String> {
+  let mut state = state.lock().await;
+  if query._tag == "login_token" {
+    return Html(format!("<ajax_response_xml_root>{}</ajax_response_xml_root>", state.login_random()));
+  }
+  Html(state.login_page(None))
+}
+
+async fn login(State(state): State<ArcMockState>, Query(query): Query<LoginQuery>, Form(form): Form<HashMap<String, String>>) -> Html<String> {
+  let mut state = state.lock().await;
+  if query._tag == "login_entry" {
+    return Html(state.login_entry(&form));
+  }
+  Html(state.login(&form))
+}
+
+async fn template(State(state): State<ArcMockState>) -> Html<String> {
+  let mut state = state.lock().await;
+  if state.session_token.is_none() {
+    return Html(state.login_page(None));
+  }
+  Html(state.page(""))
+}
+
+async fn get_page(State(state): State<ArcMockState>, Query(query): Query<PageQuery>) -> Html<String> {
+  let mut state = state.lock().await;
+  if state.session_token.is_none() {
+    return Html(state.login_page(None));
+  }
+  Html(state.get_page(&query.nextpage).unwrap_or_default())
+}
+
+async fn post_page(State(state): State<ArcMockState>, Query(query): Query<PageQuery>, Form(form): Form<HashMap<String, String>>) -> Html<String> {
+  let mut state = state.lock().await;
+  // like a logged out session, a stale `_SESSION_TOKEN` gets the login page and the form is dropped
+  if state.session_token.is_none() || form.get("_SESSION_TOKEN") != state.session_token.as_ref() {
+    return Html(state.login_page(None));
+  }
+  let lan_ip = state.config.lan_dhcp.lan_ip.clone();
+  let page = state.post_page(&query.nextpage, &form).unwrap_or_default();
+  if state.config.lan_dhcp.lan_ip != lan_ip {
+    // the router has moved, the session and the reply are lost on the way
+    state.expire_session();
+    return Html(state.login_page(None));
+  }
+  Html(page)
+}
+
+pub fn router(state: ArcMockState) -> Router {
+  Router::new()
+    .route("/", get(login_page).post(login))
+    .route("/template.gch", get(template))
+    .route("/getpage.gch", get(get_page).post(post_page))
+    .with_state(state)
+}
+
+/// a running mock router, the server is stopped when this is dropped
+#[must_use]
+pub struct MockHandle {
+  base_url: String,
+  state: ArcMockState,
+  abort: tokio::task::AbortHandle,
+}
+
+impl MockHandle {
+  pub fn base_url(&self) -> &str { &self.base_url }
+  pub fn state(&self) -> &ArcMockState { &self.state }
+}
+
+impl Drop for MockHandle {
+  fn drop(&mut self) {
+    self.abort.abort();
+  }
+}
+
+/// serve the mock router on a random port of 127.0.0.1
+pub async fn spawn(config: MockConfig) -> Result<MockHandle> {
+  let state = Arc::new(Mutex::new(MockState::new(config)));
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+  let base_url = format!("http://{}", listener.local_addr()?);
+  let app = router(state.clone());
+  let abort = tokio::spawn(async move { axum::serve(listener, app).await }).abort_handle();
+  Ok(MockHandle { base_url, state, abort })
+}
+
+/// `spawn` and a `Context` already logged in to it as `USERNAME`
+pub async fn logged_in(config: MockConfig) -> Result<(MockHandle, super::Context)> {
+  let mock = spawn(config).await?;
+  let mut ctx = super::Context::new(mock.base_url());
+  ctx.login(USERNAME, PASSWORD).await?;
+  Ok((mock, ctx))
+}
@@ -0,0 +1,158 @@
+use std::{net::{IpAddr, SocketAddr}, time::Duration};
+
+use tokio::net::{TcpStream, UdpSocket};
+
+use super::{Context, LanInfo, OnuError, PortForwardingParam, PortForwardingProtocol, PortForwardingSelector, Result};
+
+/// probe at most this many ports of a range
+pub const MAX_PROBES_PER_RULE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+  /// TCP connected, or UDP answered
+  Open,
+  /// the host is up but refused, nothing listens on the port
+  Closed,
+  /// no answer within the timeout, the host might be off
+  Unreachable,
+  /// UDP got no answer, which is normal for many services
+  Unknown,
+}
+
+impl std::fmt::Display for Reachability {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Reachability::Open => write!(f, "open"),
+      Reachability::Closed => write!(f, "closed"),
+      Reachability::Unreachable => write!(f, "unreachable"),
+      Reachability::Unknown => write!(f, "no answer"),
+    }
+  }
+}
+
+pub async fn probe_tcp(addr: SocketAddr, timeout: Duration) -> Reachability {
+  match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+    Ok(Ok(_)) => Reachability::Open,
+    Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => Reachability::Closed,
+    _ => Reachability::Unreachable,
+  }
+}
+
+/// send an empty datagram, a closed port is only told by an ICMP port unreachable
+pub async fn probe_udp(addr: SocketAddr, timeout: Duration) -> Reachability {
+  let bind: SocketAddr = if addr.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+  let Ok(socket) = UdpSocket::bind(bind).await else { return Reachability::Unknown };
+  if socket.connect(addr).await.is_err() || socket.send(&[]).await.is_err() {
+    return Reachability::Unreachable;
+  }
+  let mut buf = [0; 64];
+  match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+    Ok(Ok(_)) => Reachability::Open,
+    Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => Reachability::Closed,
+    Ok(Err(_)) => Reachability::Unreachable,
+    Err(_) => Reachability::Unknown,
+  }
+}
+
+/// the probes of one rule of `Context::port_forwarding_list`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleCheck {
+  pub param: PortForwardingParam,
+  /// `local_addr`, or the ip of `local_mac` in `Context::lan_info`
+  pub addr: Option<IpAddr>,
+  /// `(protocol, port, reachability)`, protocol is "tcp" or "udp"
+  pub probes: Vec<(&'static str, u32, Reachability)>,
+}
+
+impl RuleCheck {
+  /// nothing answered on any port, or the target has no address
+  pub fn is_dead(&self) -> bool {
+    self.addr.is_none() || self.probes.iter().all(|(_, _, r)| matches!(r, Reachability::Closed | Reachability::Unreachable))
+  }
+}
+
+/// probe the local ports of `param` from this machine, `lan` resolves mac targets
+pub async fn check_rule(param: &PortForwardingParam, lan: &[LanInfo], timeout: Duration) -> RuleCheck {
+  let addr = if param.enable_local_mac {
+    param.local_mac.as_deref().and_then(|mac| lan.iter().find(|l| l.mac.eq_ignore_ascii_case(mac))).map(|l| l.ip.as_str())
+  } else {
+    param.local_addr.as_deref()
+  }.and_then(|a| a.parse::<IpAddr>().ok());
+  let mut probes = Vec::new();
+  if let Some(addr) = addr {
+    let last = param.local_port_max.min(param.local_port_min.saturating_add(MAX_PROBES_PER_RULE - 1));
+    for port in param.local_port_min..=last {
+      let Ok(port16) = u16::try_from(port) else { continue };
+      let target = SocketAddr::new(addr, port16);
+      if param.protocol != PortForwardingProtocol::UDP {
+        probes.push(("tcp", port, probe_tcp(target, timeout).await));
+      }
+      if param.protocol != PortForwardingProtocol::TCP {
+        probes.push(("udp", port, probe_udp(target, timeout).await));
+      }
+    }
+  }
+  RuleCheck { param: param.clone(), addr, probes }
+}
+
+impl Context {
+  /// probe every rule, or the one selected by `selector`, see `check_rule`
+  pub async fn port_forwarding_check(&mut self, selector: Option<&PortForwardingSelector>, timeout: Duration) -> Result<Vec<RuleCheck>> {
+    let list = self.port_forwarding_list().await?;
+    let list = match selector {
+      Some(selector) => {
+        let (_, param) = selector.find(&list)
+          .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find {} in port forwarding list", selector)))?;
+        vec![param.clone()]
+      }
+      None => list,
+    };
+    let lan = if list.iter().any(|p| p.enable_local_mac) { self.lan_info().await? } else { Vec::new() };
+    let mut checks = Vec::new();
+    for param in &list {
+      checks.push(check_rule(param, &lan, timeout).await);
+    }
+    Ok(checks)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::{PortForwardingHost, PortForwardingPort};
+
+#[tokio::test]
+async fn test_check_rule() -> anyhow::Result<()> {
+  let timeout = Duration::from_millis(500);
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+  let open = listener.local_addr()?.port() as u32;
+  let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port() as u32;
+  let echo = UdpSocket::bind("127.0.0.1:0").await?;
+  let udp = echo.local_addr()?.port() as u32;
+  tokio::spawn(async move {
+    let mut buf = [0; 64];
+    while let Ok((n, peer)) = echo.recv_from(&mut buf).await {
+      echo.send_to(&buf[..n], peer).await.ok();
+    }
+  });
+
+  let rule = |port: u32, protocol: PortForwardingProtocol| PortForwardingParam::new(
+    "test", "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("127.0.0.1".to_string()), PortForwardingPort::Transform { remote: 10000, local: port })
+    .with_protocol(protocol);
+  let check = check_rule(&rule(open, PortForwardingProtocol::TCP), &[], timeout).await;
+  assert_eq!(check.probes, [("tcp", open, Reachability::Open)]);
+  assert!(!check.is_dead());
+  let check = check_rule(&rule(closed, PortForwardingProtocol::TCP), &[], timeout).await;
+  assert_eq!(check.probes, [("tcp", closed, Reachability::Closed)]);
+  assert!(check.is_dead());
+  let check = check_rule(&rule(udp, PortForwardingProtocol::UDP), &[], timeout).await;
+  assert_eq!(check.probes, [("udp", udp, Reachability::Open)]);
+
+  let mac = PortForwardingParam::new("mac", "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Mac("aa:bb:cc:dd:ee:09".to_string()), PortForwardingPort::Simple(open));
+  let check = check_rule(&mac, &[], timeout).await;
+  assert_eq!(check.addr, None);
+  assert!(check.is_dead());
+  drop(listener);
+  Ok(())
+}
+}
@@ -0,0 +1,135 @@
+use super::{
+  parse_transfer_meaning, serde_str_as_i32, serde_str_as_u32, wlan::WlanRadio, Context, OnuError, Result,
+};
+
+/// an access point seen by a radio of the router, parsing from `net_wlanm_scan_t.gch`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct Neighbor {
+  #[serde(rename = "BSSID")]
+  pub bssid: String,
+  /// empty for a hidden network
+  #[serde(rename = "SSID")]
+  pub ssid: String,
+  #[serde(rename = "Channel", with = "serde_str_as_u32")]
+  pub channel: u32,
+  /// dBm, e.g. -60
+  #[serde(rename = "RSSI", with = "serde_str_as_i32")]
+  pub rssi: i32,
+  /// "None", "WPA2-PSK", ...
+  #[serde(rename = "Security")]
+  pub security: String,
+}
+
+/// how crowded a channel is, lower `score` is better
+#[derive(Debug, Clone, PartialEq, tabled::Tabled)]
+pub struct ChannelScore {
+  pub channel: u32,
+  /// networks on exactly this channel
+  pub neighbors: usize,
+  /// networks on this or an overlapping channel, each weighted by its signal from 0 (-100 dBm) to 1 (-40 dBm)
+  #[tabled(display_with = "display_score")]
+  pub score: f64,
+}
+
+fn display_score(score: &f64) -> String {
+  format!("{:.2}", score)
+}
+
+/// 2.4GHz channels are 5MHz apart but 20MHz wide, so the 4 channels on either side overlap;
+/// 5GHz channels are assumed not to overlap
+fn overlap(radio: &WlanRadio, a: u32, b: u32) -> f64 {
+  let distance = a.abs_diff(b);
+  match radio.is_5ghz() {
+    true if distance == 0 => 1.0,
+    false if distance < 5 => 1.0 - distance as f64 / 5.0,
+    _ => 0.0,
+  }
+}
+
+/// every channel of `radio`, the least crowded first
+pub fn rank_channels(radio: &WlanRadio, neighbors: &[Neighbor]) -> Vec<ChannelScore> {
+  let mut ranks = radio.channels().into_iter().map(|channel| ChannelScore {
+    channel,
+    neighbors: neighbors.iter().filter(|n| n.channel == channel).count(),
+    score: neighbors.iter()
+      .map(|n| overlap(radio, channel, n.channel) * ((n.rssi + 100).clamp(0, 60) as f64 / 60.0))
+      .sum(),
+  }).collect::<Vec<_>>();
+  ranks.sort_by(|a, b| a.score.total_cmp(&b.score).then(a.neighbors.cmp(&b.neighbors)));
+  ranks
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct SurveyRequest {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(rename = "IF_INDEX")]
+  index: i32,
+}
+
+impl Context {
+  pub fn parse_site_survey(resp: &str) -> Result<Vec<Neighbor>> {
+    let page = "net_wlanm_scan_t.gch";
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse(page, "IF_INSTNUM"))?;
+    let mut list = Vec::new();
+    for i in 0..count {
+      let field = |name: &str| parse_transfer_meaning(resp, &format!("{}{}", name, i)).unwrap_or_default();
+      list.push(Neighbor {
+        bssid: field("BSSID"),
+        ssid: field("SSID"),
+        channel: field("Channel").parse().map_err(|_| OnuError::parse(page, format!("Channel{}", i)))?,
+        rssi: field("RSSI").parse().map_err(|_| OnuError::parse(page, format!("RSSI{}", i)))?,
+        security: field("Security"),
+      });
+    }
+    Ok(list)
+  }
+
+  /// let the radio selected by `key` (see `WlanRadio::matches`) scan for other networks,
+  /// clients might notice a short hiccup
+  pub async fn wlan_site_survey(&mut self, key: &str) -> Result<(WlanRadio, Vec<Neighbor>)> {
+    let radios = self.wlan_radios().await?;
+    let (index, radio) = radios.into_iter().enumerate().find(|(i, r)| r.matches(*i, key))
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find radio {}", key)))?;
+    let (err, resp) = self.post("net_wlanm_scan_t.gch").form(&SurveyRequest { action: "scan", index: index as i32 }).send().await?;
+    err.check()?;
+    Ok((radio, Self::parse_site_survey(&resp)?))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+fn neighbor(channel: u32, rssi: i32) -> Neighbor {
+  Neighbor {
+    bssid: format!("02:00:00:00:{:02x}:{:02x}", channel, -rssi),
+    ssid: format!("ap-{}", channel),
+    channel,
+    rssi,
+    security: "WPA2-PSK".to_string(),
+  }
+}
+
+#[tokio::test]
+async fn test_site_survey() -> anyhow::Result<()> {
+  let neighbors = vec![neighbor(1, -50), neighbor(6, -85), neighbor(6, -88), neighbor(11, -40), neighbor(36, -70)];
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig { wlan_neighbors: neighbors.clone(), ..Default::default() }).await?;
+
+  let (radio, list) = ctx.wlan_site_survey("2.4").await?;
+  assert_eq!(list, neighbors[..4]);
+  let ranks = rank_channels(&radio, &list);
+  assert_eq!(ranks.len(), 11);
+  assert_eq!((ranks[0].channel, ranks[0].neighbors), (6, 2));
+  assert!(ranks.iter().find(|r| r.channel == 11).unwrap().score > 0.99);
+
+  let (radio, list) = ctx.wlan_site_survey("5GHz").await?;
+  let ranks = rank_channels(&radio, &list);
+  assert_eq!(ranks.last().map(|r| (r.channel, r.neighbors)), Some((36, 1)));
+  assert_eq!(ranks[0].score, 0.0);
+  assert!(ranks.iter().all(|r| !(52..=144).contains(&r.channel)));
+  Ok(())
+}
+}
@@ -0,0 +1,421 @@
+use std::{collections::HashSet, path::Path};
+
+use super::{
+  display_option, Context, OnuError, PortForwardingAction, PortForwardingHost, PortForwardingParam, PortForwardingPort,
+  PortForwardingProtocol, PortForwardingSelector, Result,
+};
+
+/// rules carrying this tag in `description` belong to the desired state,
+/// rules without it are made by hand and never touched by `Context::port_forwarding_sync`
+pub const MANAGED_TAG: &str = "managed";
+
+/// this mod is a serde helper that parse "tcp", "udp" or "both" with `FromStr`
+mod serde_display_fromstr {
+  use serde::{self, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<T: std::fmt::Display, S: Serializer>(t: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(t)
+  }
+
+  pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+  where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+    D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+fn default_enable() -> bool { true }
+
+/// description with trailing `[tag]`s, e.g. `ssh of nas [managed]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaggedDescription {
+  pub text: String,
+  pub tags: Vec<String>,
+}
+
+impl TaggedDescription {
+  pub fn parse(s: &str) -> Self {
+    let mut text = s.trim_end();
+    let mut tags = Vec::new();
+    while let Some(rest) = text.strip_suffix(']') {
+      let Some(pos) = rest.rfind('[') else { break };
+      tags.insert(0, rest[pos+1..].to_string());
+      text = rest[..pos].trim_end();
+    }
+    Self { text: text.to_string(), tags }
+  }
+
+  pub fn has_tag(&self, tag: &str) -> bool {
+    self.tags.iter().any(|t| t == tag)
+  }
+
+  pub fn add_tag(&mut self, tag: &str) {
+    if !self.has_tag(tag) {
+      self.tags.push(tag.to_string());
+    }
+  }
+
+  /// `value` of a `[key=value]` tag
+  pub fn tag_value(&self, key: &str) -> Option<&str> {
+    self.tags.iter().find_map(|t| t.strip_prefix(key)?.strip_prefix('='))
+  }
+
+  /// replace or add the `[key=value]` tag
+  pub fn set_tag_value(&mut self, key: &str, value: &str) {
+    self.tags.retain(|t| t.strip_prefix(key).is_none_or(|rest| !rest.starts_with('=')));
+    self.tags.push(format!("{}={}", key, value));
+  }
+}
+
+impl std::fmt::Display for TaggedDescription {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.text)?;
+    for (i, tag) in self.tags.iter().enumerate() {
+      let sep = if i == 0 && self.text.is_empty() { "" } else { " " };
+      write!(f, "{}[{}]", sep, tag)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn is_managed(param: &PortForwardingParam) -> bool {
+  param.description.as_deref().map(TaggedDescription::parse).is_some_and(|d| d.has_tag(MANAGED_TAG))
+}
+
+/// a rule of the desired state, fields are the same as `PortForwardingParam`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DesiredRule {
+  pub name: String,
+  #[serde(default = "default_enable")]
+  pub enable: bool,
+  /// "tcp", "udp" or "both"
+  #[serde(with = "serde_display_fromstr")]
+  pub protocol: PortForwardingProtocol,
+  /// "IGD.WD1.***", see `WancInfo::view_name`
+  pub wan_interface: String,
+  pub remote_addr_min: Option<String>,
+  pub remote_addr_max: Option<String>,
+  pub remote_port_min: u32,
+  /// defaults to `remote_port_min`
+  pub remote_port_max: Option<u32>,
+  /// `{ host = "192.168.1.4" }` or `{ mac = "aa:bb:cc:dd:ee:01" }`, `!host 192.168.1.4` in yaml
+  pub lan: PortForwardingHost,
+  /// defaults to `remote_port_min`
+  pub local_port_min: Option<u32>,
+  /// defaults to cover as many ports as `remote_port_min..=remote_port_max`
+  pub local_port_max: Option<u32>,
+  /// without the `[managed]` tag, which is added on submit
+  pub description: Option<String>,
+}
+
+impl DesiredRule {
+  /// overwrite the fields of `base` with this rule, fields the desired state does not know
+  /// (`lease_duration`, `port_map_creator` and other description tags) are kept
+  pub fn to_param(&self, base: Option<&PortForwardingParam>) -> PortForwardingParam {
+    let remote_port_max = self.remote_port_max.unwrap_or(self.remote_port_min);
+    let local_port_min = self.local_port_min.unwrap_or(self.remote_port_min);
+    let local_port_max = self.local_port_max.unwrap_or(local_port_min + remote_port_max.saturating_sub(self.remote_port_min));
+    let mut description = base.and_then(|p| p.description.as_deref()).map(TaggedDescription::parse).unwrap_or_default();
+    description.text = self.description.clone().unwrap_or_default();
+    description.add_tag(MANAGED_TAG);
+    let port = PortForwardingPort::Multiple { remote: (self.remote_port_min, remote_port_max), local: (local_port_min, local_port_max) };
+    let mut param = PortForwardingParam {
+      remote_addr_min: self.remote_addr_min.clone(),
+      remote_addr_max: self.remote_addr_max.clone(),
+      ..PortForwardingParam::new(&self.name, &self.wan_interface, self.lan.clone(), port)
+        .with_protocol(self.protocol.clone())
+        .with_enable(self.enable)
+        .with_description(&description.to_string())
+    };
+    if let Some(base) = base {
+      param.local_addr = param.local_addr.or_else(|| base.local_addr.clone());
+      param.local_mac = param.local_mac.or_else(|| base.local_mac.clone());
+      param.lease_duration = base.lease_duration.clone();
+      param.port_map_creator = base.port_map_creator.clone();
+    }
+    param
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesiredFormat {
+  Json, Toml, Yaml,
+}
+
+impl DesiredFormat {
+  /// guess from the extension, json by default
+  pub fn from_path(path: &Path) -> Self {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("toml") => Self::Toml,
+      Some("yaml" | "yml") => Self::Yaml,
+      _ => Self::Json,
+    }
+  }
+}
+
+/// the desired-state file
+/// ```toml
+/// [[port_forwarding]]
+/// name = "ssh"
+/// protocol = "tcp"
+/// wan_interface = "IGD.WD1.WCD3.WCPPP1"
+/// remote_port_min = 10022
+/// local_port_min = 22
+/// lan = { host = "192.168.1.4" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DesiredState {
+  #[serde(default)]
+  pub port_forwarding: Vec<DesiredRule>,
+}
+
+impl DesiredState {
+  pub fn parse(content: &str, format: DesiredFormat) -> Result<Self, String> {
+    match format {
+      DesiredFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+      DesiredFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+      DesiredFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+    }
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    Self::parse(&content, DesiredFormat::from_path(path))
+      .map_err(|reason| OnuError::Load { path: path.display().to_string(), reason })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum SyncOp {
+  Add(PortForwardingParam),
+  Change { index: u32, from: PortForwardingParam, to: PortForwardingParam },
+  Remove { index: u32, param: PortForwardingParam },
+}
+
+/// steps to turn the current list into the desired state,
+/// ordered as changes, removes (from the last index) and adds,
+/// rules are followed by fingerprint when submitting, `index` is what the plan was made from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncPlan {
+  pub ops: Vec<SyncOp>,
+}
+
+fn display_range(min: u32, max: u32) -> String {
+  if min == max { min.to_string() } else { format!("{}:{}", min, max) }
+}
+
+/// the router reports an empty field as "NULL"
+fn display_field(o: &Option<String>) -> String {
+  display_option(&o.as_deref().filter(|s| *s != "NULL"))
+}
+
+fn display_lan(param: &PortForwardingParam) -> String {
+  display_field(if param.enable_local_mac { &param.local_mac } else { &param.local_addr })
+}
+
+/// `(field, from, to)` of every field `Context::port_forwarding_sync` cares about
+pub fn diff(from: &PortForwardingParam, to: &PortForwardingParam) -> Vec<(&'static str, String, String)> {
+  let fields = |p: &PortForwardingParam| [
+    ("enable", p.enable.to_string()),
+    ("protocol", p.protocol.to_string()),
+    ("wan_interface", p.wan_interface.clone()),
+    ("remote_addr_min", display_field(&p.remote_addr_min)),
+    ("remote_addr_max", display_field(&p.remote_addr_max)),
+    ("remote_port", display_range(p.remote_port_min, p.remote_port_max)),
+    ("lan", display_lan(p)),
+    ("local_port", display_range(p.local_port_min, p.local_port_max)),
+    ("description", display_field(&p.description)),
+  ];
+  fields(from).into_iter().zip(fields(to))
+    .filter(|((_, a), (_, b))| a != b)
+    .map(|((field, a), (_, b))| (field, a, b))
+    .collect()
+}
+
+impl SyncPlan {
+  /// match managed rules by name, a desired rule named like a hand-made one is refused
+  pub fn new(desired: &DesiredState, current: &[PortForwardingParam]) -> Result<Self> {
+    let mut changes = Vec::new();
+    let mut adds = Vec::new();
+    let mut names = HashSet::new();
+    let mut matched = HashSet::new();
+    for rule in &desired.port_forwarding {
+      if !names.insert(rule.name.as_str()) {
+        return Err(OnuError::InvalidRequest(format!("{:?} is duplicated in the desired state", rule.name)));
+      }
+      match current.iter().enumerate().find(|(_, p)| p.name == rule.name) {
+        Some((_, p)) if !is_managed(p) =>
+          return Err(OnuError::InvalidRequest(format!("{:?} exists but is not managed, rename or delete it first", rule.name))),
+        Some((i, p)) => {
+          matched.insert(i);
+          let to = rule.to_param(Some(p));
+          if !diff(p, &to).is_empty() {
+            changes.push(SyncOp::Change { index: i as u32, from: p.clone(), to });
+          }
+        }
+        None => adds.push(SyncOp::Add(rule.to_param(None))),
+      }
+    }
+    let removes = current.iter().enumerate().rev()
+      .filter(|(i, p)| is_managed(p) && !matched.contains(i))
+      .map(|(i, p)| SyncOp::Remove { index: i as u32, param: p.clone() });
+    let ops = changes.into_iter().chain(removes).chain(adds).collect();
+    Ok(Self { ops })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ops.is_empty()
+  }
+}
+
+impl std::fmt::Display for SyncPlan {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let summary = |p: &PortForwardingParam| format!(
+      "{} {} -> {}:{}", p.protocol, display_range(p.remote_port_min, p.remote_port_max),
+      display_lan(p), display_range(p.local_port_min, p.local_port_max));
+    for op in &self.ops {
+      match op {
+        SyncOp::Add(p) => writeln!(f, "+ {} ({})", p.name, summary(p))?,
+        SyncOp::Remove { index, param } => writeln!(f, "- {} #{} ({})", param.name, index, summary(param))?,
+        SyncOp::Change { index, from, to } => {
+          writeln!(f, "~ {} #{}", from.name, index)?;
+          for (field, a, b) in diff(from, to) {
+            writeln!(f, "    {}: {:?} -> {:?}", field, a, b)?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Context {
+  /// diff `desired` against `Context::port_forwarding_list`
+  pub async fn port_forwarding_plan(&mut self, desired: &DesiredState) -> Result<SyncPlan> {
+    let list = self.port_forwarding_list().await?;
+    SyncPlan::new(desired, &list)
+  }
+
+  /// run the new/apply/delete requests of `plan`, returns the final list
+  pub async fn port_forwarding_sync(&mut self, plan: &SyncPlan) -> Result<Vec<PortForwardingParam>> {
+    let mut list = None;
+    for op in &plan.ops {
+      debug!("sync port forwarding: {:?}", op);
+      list = Some(match op {
+        SyncOp::Change { from, to, .. } =>
+          self.port_forwarding_apply(&PortForwardingSelector::Fingerprint(from.fingerprint()), to.clone()).await?,
+        SyncOp::Remove { param, .. } =>
+          self.port_forwarding_remove(&PortForwardingSelector::Fingerprint(param.fingerprint())).await?,
+        SyncOp::Add(param) => self.port_forwarding(PortForwardingAction::New, param.clone()).await?,
+      });
+    }
+    match list {
+      Some(list) => Ok(list),
+      None => self.port_forwarding_list().await,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[test]
+fn test_tagged_description() {
+  let d = TaggedDescription::parse("ssh of nas [managed] [x=1]");
+  assert_eq!(d.text, "ssh of nas");
+  assert_eq!(d.tags, vec!["managed", "x=1"]);
+  assert_eq!(d.to_string(), "ssh of nas [managed] [x=1]");
+  assert_eq!(d.tag_value("x"), Some("1"));
+  let mut d = d;
+  d.set_tag_value("x", "2");
+  assert_eq!(d.to_string(), "ssh of nas [managed] [x=2]");
+  let mut d = TaggedDescription::parse("");
+  d.add_tag(MANAGED_TAG);
+  assert_eq!(d.to_string(), "[managed]");
+  assert_eq!(TaggedDescription::parse("[managed]"), d);
+}
+
+#[test]
+fn test_desired_formats() {
+  let toml = r#"
+[[port_forwarding]]
+name = "ssh"
+protocol = "tcp"
+wan_interface = "IGD.WD1.WCD3.WCPPP1"
+remote_port_min = 10022
+local_port_min = 22
+lan = { host = "192.168.1.4" }
+"#;
+  let yaml = r#"
+port_forwarding:
+  - name: ssh
+    protocol: tcp
+    wan_interface: IGD.WD1.WCD3.WCPPP1
+    remote_port_min: 10022
+    local_port_min: 22
+    lan: !host 192.168.1.4
+"#;
+  let json = r#"{"port_forwarding": [{"name": "ssh", "protocol": "TCP", "wan_interface": "IGD.WD1.WCD3.WCPPP1",
+    "remote_port_min": 10022, "local_port_min": 22, "lan": {"host": "192.168.1.4"}}]}"#;
+  let state = DesiredState::parse(toml, DesiredFormat::Toml).unwrap();
+  assert_eq!(DesiredState::parse(yaml, DesiredFormat::Yaml).unwrap(), state);
+  assert_eq!(DesiredState::parse(json, DesiredFormat::Json).unwrap(), state);
+  let param = state.port_forwarding[0].to_param(None);
+  assert!(param.enable);
+  assert_eq!((param.remote_port_min, param.remote_port_max, param.local_port_min, param.local_port_max), (10022, 10022, 22, 22));
+  assert_eq!(param.description.as_deref(), Some("[managed]"));
+  assert!(DesiredState::parse("port_forwarding = 1", DesiredFormat::Toml).is_err());
+}
+
+#[tokio::test]
+async fn test_port_forwarding_sync() -> anyhow::Result<()> {
+  let rule = |name: &str, remote: u32, local: u32| DesiredRule {
+    name: name.to_string(),
+    enable: true,
+    protocol: PortForwardingProtocol::TCP,
+    wan_interface: "IGD.WD1.WCD3.WCPPP1".to_string(),
+    remote_addr_min: None,
+    remote_addr_max: None,
+    remote_port_min: remote,
+    remote_port_max: None,
+    lan: PortForwardingHost::Host("192.168.1.4".to_string()),
+    local_port_min: Some(local),
+    local_port_max: None,
+    description: None,
+  };
+  let hand = PortForwardingParam { description: Some("made by hand".to_string()), ..rule("hand", 8080, 80).to_param(None) };
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig {
+    port_forwarding: vec![hand, rule("old", 8443, 443).to_param(None), rule("ssh", 10022, 22).to_param(None)],
+    ..Default::default()
+  }).await?;
+
+  let desired = DesiredState { port_forwarding: vec![
+    DesiredRule { enable: false, ..rule("ssh", 10022, 22) },
+    rule("rdp", 13389, 3389),
+  ] };
+  let plan = ctx.port_forwarding_plan(&desired).await?;
+  info!("plan:\n{}", plan);
+  assert!(matches!(plan.ops.as_slice(), [
+    SyncOp::Change { index: 2, .. }, SyncOp::Remove { index: 1, .. }, SyncOp::Add(_),
+  ]));
+
+  let list = ctx.port_forwarding_sync(&plan).await?;
+  let names = list.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+  assert_eq!(names, ["hand", "ssh", "rdp"]);
+  assert_eq!(list[0].description.as_deref(), Some("made by hand"));
+  assert!(!list[1].enable);
+  assert!(ctx.port_forwarding_plan(&desired).await?.is_empty());
+
+  let conflict = DesiredState { port_forwarding: vec![rule("hand", 8080, 80)] };
+  assert!(matches!(ctx.port_forwarding_plan(&conflict).await, Err(OnuError::InvalidRequest(_))));
+  Ok(())
+}
+}
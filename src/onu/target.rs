@@ -0,0 +1,148 @@
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+
+use super::{validate::{is_valid_mac, normalize_mac}, Context, LanInfo, OnuError, PortForwardingHost, Result};
+
+/// what a rule forwards to, as given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortForwardingTarget {
+  /// a literal ipv4 or mac address
+  Addr(PortForwardingHost),
+  /// `LanInfo::name` in the DHCP table
+  Name(String),
+  /// the machine running this, found by its interface mac (or its ip towards the router)
+  Local,
+}
+
+impl std::fmt::Display for PortForwardingTarget {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PortForwardingTarget::Addr(host) => write!(f, "{}", host),
+      PortForwardingTarget::Name(name) => write!(f, "{}", name),
+      PortForwardingTarget::Local => write!(f, "self"),
+    }
+  }
+}
+
+impl std::str::FromStr for PortForwardingTarget {
+  type Err = &'static str;
+  /// `self`, an ipv4 address, a mac address (see `normalize_mac`), otherwise a hostname
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s == "self" {
+      Ok(Self::Local)
+    } else if s.parse::<Ipv4Addr>().is_ok() {
+      Ok(Self::Addr(PortForwardingHost::Host(s.to_string())))
+    } else if let Some(mac) = normalize_mac(s) {
+      Ok(Self::Addr(PortForwardingHost::Mac(mac)))
+    } else if s.is_empty() {
+      Err("empty target")
+    } else {
+      Ok(Self::Name(s.to_string()))
+    }
+  }
+}
+
+pub(super) fn same_mac(a: &str, b: &str) -> bool {
+  a.replace('-', ":").eq_ignore_ascii_case(&b.replace('-', ":"))
+}
+
+/// mac addresses of the interfaces of this machine, empty if not available (non-linux)
+pub fn local_macs() -> Vec<String> {
+  let Ok(dir) = std::fs::read_dir("/sys/class/net") else { return Vec::new() };
+  dir.filter_map(|entry| std::fs::read_to_string(entry.ok()?.path().join("address")).ok())
+    .map(|mac| mac.trim().to_string())
+    .filter(|mac| is_valid_mac(mac) && mac != "00:00:00:00:00:00")
+    .collect()
+}
+
+impl PortForwardingTarget {
+  /// look the target up in `lan`, `local_macs` and `local_ip` describe this machine for `Local`,
+  /// with `pin_mac` the result is always a `PortForwardingHost::Mac`
+  pub fn resolve(&self, lan: &[LanInfo], local_macs: &[String], local_ip: Option<IpAddr>, pin_mac: bool) -> Result<PortForwardingHost> {
+    let info = match self {
+      PortForwardingTarget::Addr(PortForwardingHost::Mac(mac)) => return Ok(PortForwardingHost::Mac(mac.clone())),
+      PortForwardingTarget::Addr(host) if !pin_mac => return Ok(host.clone()),
+      PortForwardingTarget::Addr(PortForwardingHost::Host(ip)) => lan.iter().find(|l| &l.ip == ip),
+      PortForwardingTarget::Name(name) => {
+        let mut found = lan.iter().filter(|l| l.name.eq_ignore_ascii_case(name));
+        let info = found.next();
+        if found.any(|l| Some(&l.mac) != info.map(|i| &i.mac)) {
+          return Err(OnuError::InvalidRequest(format!("more than one host named {:?} in lan info", name)));
+        }
+        info
+      }
+      PortForwardingTarget::Local => lan.iter()
+        .find(|l| local_macs.iter().any(|mac| same_mac(mac, &l.mac)))
+        .or_else(|| lan.iter().find(|l| Some(l.ip.as_str()) == local_ip.map(|ip| ip.to_string()).as_deref())),
+    };
+    let info = info.ok_or_else(|| OnuError::InvalidRequest(format!("cannot find host {} in lan info", self)))?;
+    if pin_mac {
+      Ok(PortForwardingHost::Mac(info.mac.clone()))
+    } else {
+      Ok(PortForwardingHost::Host(info.ip.clone()))
+    }
+  }
+}
+
+impl Context {
+  /// the local address used to reach the router, no packet is sent
+  pub fn local_ip(&self) -> Option<IpAddr> {
+    let url = reqwest::Url::parse(self.base_url()).ok()?;
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect((url.host_str()?, url.port_or_known_default()?)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+  }
+
+  /// resolve hostnames and `self` through `Context::lan_info`
+  pub async fn resolve_target(&mut self, target: &PortForwardingTarget, pin_mac: bool) -> Result<PortForwardingHost> {
+    let lan = match target {
+      PortForwardingTarget::Addr(PortForwardingHost::Mac(_)) => Vec::new(),
+      PortForwardingTarget::Addr(_) if !pin_mac => Vec::new(),
+      _ => self.lan_info().await?,
+    };
+    let (local_macs, local_ip) = match target {
+      PortForwardingTarget::Local => (local_macs(), self.local_ip()),
+      _ => (Vec::new(), None),
+    };
+    target.resolve(&lan, &local_macs, local_ip, pin_mac)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[test]
+fn test_from_str() {
+  assert_eq!("self".parse(), Ok(PortForwardingTarget::Local));
+  assert_eq!("192.168.1.4".parse(), Ok(PortForwardingTarget::Addr(PortForwardingHost::Host("192.168.1.4".to_string()))));
+  assert_eq!("AA-BB-CC-DD-EE-01".parse(), Ok(PortForwardingTarget::Addr(PortForwardingHost::Mac("aa:bb:cc:dd:ee:01".to_string()))));
+  assert_eq!("nas".parse(), Ok(PortForwardingTarget::Name("nas".to_string())));
+}
+
+#[test]
+fn test_resolve_local() -> anyhow::Result<()> {
+  let lan = mock::MockConfig::default().lan;
+  let local = PortForwardingTarget::Local;
+  let macs = vec!["02:42:00:00:00:01".to_string(), "AA:BB:CC:DD:EE:02".to_string()];
+  assert_eq!(local.resolve(&lan, &macs, None, false)?, PortForwardingHost::Host("192.168.1.5".to_string()));
+  let ip = Some("192.168.1.4".parse()?);
+  assert_eq!(local.resolve(&lan, &[], ip, true)?, PortForwardingHost::Mac("aa:bb:cc:dd:ee:01".to_string()));
+  assert!(local.resolve(&lan, &[], None, false).is_err());
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_resolve_target() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let nas = PortForwardingTarget::Name("NAS".to_string());
+  assert_eq!(ctx.resolve_target(&nas, false).await?, PortForwardingHost::Host("192.168.1.4".to_string()));
+  assert_eq!(ctx.resolve_target(&nas, true).await?, PortForwardingHost::Mac("aa:bb:cc:dd:ee:01".to_string()));
+  let phone = PortForwardingTarget::Addr(PortForwardingHost::Host("192.168.1.5".to_string()));
+  assert_eq!(ctx.resolve_target(&phone, true).await?, PortForwardingHost::Mac("aa:bb:cc:dd:ee:02".to_string()));
+  let err = ctx.resolve_target(&PortForwardingTarget::Name("printer".to_string()), false).await.unwrap_err();
+  assert!(matches!(err, OnuError::InvalidRequest(_)), "{:?}", err);
+  assert_eq!(ctx.local_ip(), Some("127.0.0.1".parse()?));
+  Ok(())
+}
+}
@@ -0,0 +1,134 @@
+use super::{
+  parse_transfer_meaning, serde_str01_as_bool, serde_str_as_u32, serde_strnull_as_option, display_option,
+  Context, OnuError, PortForwardingProtocol, Result,
+};
+
+/// a mapping a LAN client created by itself through UPnP IGD, not to be confused with
+/// the virtual servers of `Context::port_forwarding_list`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct UPnPMapping {
+  #[serde(rename = "Enable", with = "serde_str01_as_bool")]
+  pub enable: bool,
+  #[serde(rename = "Protocol")]
+  pub protocol: PortForwardingProtocol,
+  /// only this remote host is forwarded, `None` for any
+  #[serde(rename = "RemoteHost", with = "serde_strnull_as_option")]
+  #[tabled(display_with = "display_option")]
+  pub remote_host: Option<String>,
+  #[serde(rename = "ExtPort", with = "serde_str_as_u32")]
+  pub external_port: u32,
+  #[serde(rename = "InternalClient")]
+  pub internal_client: String,
+  #[serde(rename = "IntPort", with = "serde_str_as_u32")]
+  pub internal_port: u32,
+  #[serde(rename = "Description")]
+  pub description: String,
+  /// seconds left, 0 never expires
+  #[serde(rename = "LeaseDuration", with = "serde_str_as_u32")]
+  #[tabled(display_with = "display_lease")]
+  pub lease_duration: u32,
+}
+
+fn display_lease(lease: &u32) -> String {
+  match lease {
+    0 => "permanent".to_string(),
+    s => humantime::format_duration(std::time::Duration::from_secs(*s as u64)).to_string(),
+  }
+}
+
+/// parsing from `app_upnp_conf_t.gch`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UPnPStatus {
+  /// Transfer_meaning('Enable', '1'), the UPnP service itself
+  pub enable: bool,
+  pub mappings: Vec<UPnPMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct UPnPRequest {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(rename = "Enable", with = "serde_str01_as_bool")]
+  enable: bool,
+}
+
+impl Context {
+  pub fn parse_upnp_status(resp: &str) -> Result<UPnPStatus> {
+    let page = "app_upnp_conf_t.gch";
+    let enable = parse_transfer_meaning(resp, "Enable").ok_or_else(|| OnuError::parse(page, "Enable"))? == "1";
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse(page, "IF_INSTNUM"))?;
+    let mut mappings = Vec::new();
+    for i in 0..count {
+      let field = |name: &str| parse_transfer_meaning(resp, &format!("{}{}", name, i));
+      let number = |name: &str| field(name).unwrap_or_default().parse::<u32>().map_err(|_| OnuError::parse(page, format!("{}{}", name, i)));
+      mappings.push(UPnPMapping {
+        enable: field("Enable").unwrap_or_default() == "1",
+        protocol: match field("Protocol").unwrap_or_default().as_str() {
+          "0" => PortForwardingProtocol::TCP,
+          "1" => PortForwardingProtocol::UDP,
+          "2" => PortForwardingProtocol::Both,
+          _ => return Err(OnuError::parse(page, format!("Protocol{}", i))),
+        },
+        remote_host: field("RemoteHost").filter(|h| !h.is_empty() && h != "NULL"),
+        external_port: number("ExtPort")?,
+        internal_client: field("InternalClient").unwrap_or_default(),
+        internal_port: number("IntPort")?,
+        description: field("Description").unwrap_or_default(),
+        lease_duration: number("LeaseDuration")?,
+      });
+    }
+    Ok(UPnPStatus { enable, mappings })
+  }
+
+  pub async fn upnp_status(&mut self) -> Result<UPnPStatus> {
+    let (_, resp) = self.fetch("app_upnp_conf_t.gch").await?;
+    Self::parse_upnp_status(&resp)
+  }
+
+  /// turn the UPnP service on or off, the router drops every mapping when it is off
+  pub async fn upnp_set_enable(&mut self, enable: bool) -> Result<UPnPStatus> {
+    let (err, resp) = self.post("app_upnp_conf_t.gch").form(&UPnPRequest { action: "apply", enable }).send().await?;
+    err.check()?;
+    let status = Self::parse_upnp_status(&resp)?;
+    if status.enable != enable {
+      return Err(OnuError::Unconfirmed { action: "apply", rule: "upnp".to_string() });
+    }
+    Ok(status)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[tokio::test]
+async fn test_upnp() -> anyhow::Result<()> {
+  let mapping = UPnPMapping {
+    enable: true,
+    protocol: PortForwardingProtocol::UDP,
+    remote_host: None,
+    external_port: 51413,
+    internal_client: "192.168.1.4".to_string(),
+    internal_port: 51413,
+    description: "Transmission at 51413".to_string(),
+    lease_duration: 3600,
+  };
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig {
+    upnp: UPnPStatus { enable: true, mappings: vec![mapping.clone()] },
+    ..Default::default()
+  }).await?;
+  let status = ctx.upnp_status().await?;
+  assert!(status.enable);
+  assert_eq!(status.mappings, [mapping]);
+  assert_eq!(display_lease(&status.mappings[0].lease_duration), "1h");
+  assert!(ctx.port_forwarding_list().await?.is_empty());
+
+  let status = ctx.upnp_set_enable(false).await?;
+  assert!(!status.enable);
+  assert!(status.mappings.is_empty());
+  assert!(ctx.upnp_set_enable(true).await?.enable);
+  Ok(())
+}
+}
@@ -0,0 +1,287 @@
+use std::net::Ipv4Addr;
+
+use super::{OnuError, PortForwardingParam, PortForwardingProtocol, Result};
+
+/// a problem found in a rule before it is submitted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+  EmptyName,
+  /// `min > max`, or out of `1..=65535`
+  InvalidPortRange { field: &'static str, min: u32, max: u32 },
+  /// `PortForwardingPort::Multiple` with ranges of different length
+  PortRangeMismatch { remote: (u32, u32), local: (u32, u32) },
+  /// the router itself listens on `port` of the WAN
+  ReservedPort { port: u32, usage: String },
+  InvalidAddress { field: &'static str, value: String },
+  InvalidMac(String),
+  /// the external range collides with another rule on the same WAN and protocol
+  Overlap { index: u32, name: String, remote: (u32, u32) },
+  /// the router holds at most `limit` rules
+  TooManyEntries { limit: usize },
+  /// not a contiguous netmask like 255.255.255.0
+  InvalidSubnetMask(String),
+  /// the address is not in the subnet of the LAN ip
+  OutsideSubnet { field: &'static str, value: String },
+  /// the DHCP pool would hand out the address of the router itself
+  AddressInPool(String),
+  /// 1 to 32 bytes
+  InvalidSsid(String),
+  /// 8 to 63 printable ascii characters, or 64 hex digits
+  InvalidPassphrase,
+  /// not a channel of the band
+  InvalidChannel { band: String, channel: u32 },
+}
+
+impl std::fmt::Display for ValidationIssue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ValidationIssue::EmptyName => write!(f, "name is empty"),
+      ValidationIssue::InvalidPortRange { field, min, max } => write!(f, "{} {}:{} is not a valid port range", field, min, max),
+      ValidationIssue::PortRangeMismatch { remote, local } =>
+        write!(f, "external ports {}:{} and local ports {}:{} differ in length", remote.0, remote.1, local.0, local.1),
+      ValidationIssue::ReservedPort { port, usage } => write!(f, "port {} is used by the router for {}", port, usage),
+      ValidationIssue::InvalidAddress { field, value } => write!(f, "{} {:?} is not an ipv4 address", field, value),
+      ValidationIssue::InvalidMac(mac) => write!(f, "{:?} is not a mac address", mac),
+      ValidationIssue::Overlap { index, name, remote } =>
+        write!(f, "external ports overlap with #{} {:?} ({}:{})", index, name, remote.0, remote.1),
+      ValidationIssue::TooManyEntries { limit } => write!(f, "the router holds at most {} rules", limit),
+      ValidationIssue::InvalidSubnetMask(mask) => write!(f, "{:?} is not a subnet mask", mask),
+      ValidationIssue::OutsideSubnet { field, value } => write!(f, "{} {} is outside the lan subnet", field, value),
+      ValidationIssue::AddressInPool(addr) => write!(f, "lan ip {} is inside the dhcp pool", addr),
+      ValidationIssue::InvalidSsid(ssid) => write!(f, "ssid {:?} is not 1 to 32 bytes", ssid),
+      ValidationIssue::InvalidPassphrase => write!(f, "passphrase is not 8 to 63 printable ascii characters"),
+      ValidationIssue::InvalidChannel { band, channel } => write!(f, "{} is not a channel of {}", channel, band),
+    }
+  }
+}
+
+/// checks done by `Context::port_forwarding` and friends before any POST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validator {
+  /// ports the router serves on the WAN itself, the web admin (80) and telnet (23) are
+  /// only reachable from the WAN with remote management on, so they are not reserved by default
+  pub reserved_ports: Vec<(u32, String)>,
+  /// max number of rules of `app_virtual_conf_t.gch`
+  pub max_entries: usize,
+}
+
+impl Default for Validator {
+  fn default() -> Self {
+    Self {
+      reserved_ports: vec![
+        (7547, "TR-069".to_string()),
+        (58000, "TR-069 connection request".to_string()),
+      ],
+      max_entries: 32,
+    }
+  }
+}
+
+/// `None`, "" and "NULL" are all not set
+fn non_empty(o: &Option<String>) -> Option<&str> {
+  match o.as_deref() {
+    None | Some("") | Some("NULL") => None,
+    Some(s) => Some(s),
+  }
+}
+
+/// `aa:bb:cc:dd:ee:ff` or `aa-bb-cc-dd-ee-ff`
+pub fn is_valid_mac(s: &str) -> bool {
+  let parts = s.split([':', '-']).collect::<Vec<_>>();
+  parts.len() == 6 && parts.iter().all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// lowercase with colons, the form the router shows in its own tables, `None` if `s` is not a mac
+pub fn normalize_mac(s: &str) -> Option<String> {
+  is_valid_mac(s).then(|| s.to_ascii_lowercase().replace('-', ":"))
+}
+
+fn protocol_overlaps(a: &PortForwardingProtocol, b: &PortForwardingProtocol) -> bool {
+  a == b || *a == PortForwardingProtocol::Both || *b == PortForwardingProtocol::Both
+}
+
+impl Validator {
+  /// every problem of `param` as the rule at `index` (`None` for a new rule) among `list`
+  pub fn check(&self, param: &PortForwardingParam, index: Option<u32>, list: &[PortForwardingParam]) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    // an apply keeping the external ports of the rule, e.g. enable or disable,
+    // cannot add a collision, rules made before a port got reserved stay editable
+    let unchanged = index.and_then(|i| list.get(i as usize)).is_some_and(|old| {
+      (old.remote_port_min, old.remote_port_max) == (param.remote_port_min, param.remote_port_max)
+        && old.wan_interface == param.wan_interface && old.protocol == param.protocol
+    });
+    if param.name.trim().is_empty() {
+      issues.push(ValidationIssue::EmptyName);
+    }
+
+    let remote = (param.remote_port_min, param.remote_port_max);
+    let local = (param.local_port_min, param.local_port_max);
+    let mut ranges_valid = true;
+    for (field, (min, max)) in [("external port", remote), ("local port", local)] {
+      if min == 0 || min > max || max > 65535 {
+        issues.push(ValidationIssue::InvalidPortRange { field, min, max });
+        ranges_valid = false;
+      }
+    }
+    if ranges_valid && remote.1 - remote.0 != local.1 - local.0 {
+      issues.push(ValidationIssue::PortRangeMismatch { remote, local });
+    }
+    for (port, usage) in &self.reserved_ports {
+      if !unchanged && (remote.0..=remote.1).contains(port) {
+        issues.push(ValidationIssue::ReservedPort { port: *port, usage: usage.clone() });
+      }
+    }
+
+    let mut remote_addr = Vec::new();
+    for (field, value) in [("remote host min", &param.remote_addr_min), ("remote host max", &param.remote_addr_max)] {
+      if let Some(value) = non_empty(value) {
+        match value.parse::<Ipv4Addr>() {
+          Ok(addr) => remote_addr.push(addr),
+          Err(_) => issues.push(ValidationIssue::InvalidAddress { field, value: value.to_string() }),
+        }
+      }
+    }
+    if let [min, max] = remote_addr[..] {
+      if min > max {
+        issues.push(ValidationIssue::InvalidAddress { field: "remote host range", value: format!("{}-{}", min, max) });
+      }
+    }
+    if param.enable_local_mac {
+      let mac = non_empty(&param.local_mac).unwrap_or_default();
+      if !is_valid_mac(mac) {
+        issues.push(ValidationIssue::InvalidMac(mac.to_string()));
+      }
+    } else {
+      let addr = non_empty(&param.local_addr).unwrap_or_default();
+      if addr.parse::<Ipv4Addr>().is_err() {
+        issues.push(ValidationIssue::InvalidAddress { field: "local host", value: addr.to_string() });
+      }
+    }
+
+    for (i, other) in list.iter().enumerate() {
+      if unchanged || Some(i as u32) == index || other.wan_interface != param.wan_interface || !protocol_overlaps(&other.protocol, &param.protocol) {
+        continue;
+      }
+      if other.remote_port_min <= remote.1 && remote.0 <= other.remote_port_max {
+        issues.push(ValidationIssue::Overlap { index: i as u32, name: other.name.clone(), remote: (other.remote_port_min, other.remote_port_max) });
+      }
+    }
+    if index.is_none() && list.len() >= self.max_entries {
+      issues.push(ValidationIssue::TooManyEntries { limit: self.max_entries });
+    }
+    issues
+  }
+
+  /// the first block of `len` external ports inside `range` that overlaps neither a rule of `list`
+  /// on the same WAN and protocol nor a reserved port
+  pub fn free_external_ports(&self, list: &[PortForwardingParam], protocol: &PortForwardingProtocol, wan_interface: &str, len: u32, range: (u32, u32)) -> Option<(u32, u32)> {
+    if len == 0 {
+      return None
+    }
+    let mut used = list.iter()
+      .filter(|p| p.wan_interface == wan_interface && protocol_overlaps(&p.protocol, protocol))
+      .map(|p| (p.remote_port_min, p.remote_port_max))
+      .chain(self.reserved_ports.iter().map(|(port, _)| (*port, *port)))
+      .collect::<Vec<_>>();
+    used.sort();
+    let mut start = range.0.max(1);
+    for (min, max) in used {
+      if start + len - 1 < min {
+        break;
+      }
+      if max >= start {
+        start = max + 1;
+      }
+    }
+    let end = start.checked_add(len - 1)?;
+    (end <= range.1.min(65535)).then_some((start, end))
+  }
+
+  /// `OnuError::Validation` with every issue of `Validator::check`
+  pub fn validate(&self, param: &PortForwardingParam, index: Option<u32>, list: &[PortForwardingParam]) -> Result<()> {
+    let issues = self.check(param, index, list);
+    if issues.is_empty() {
+      return Ok(())
+    }
+    Err(OnuError::Validation(issues))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::{PortForwardingHost, PortForwardingPort};
+
+fn rule(name: &str, protocol: PortForwardingProtocol, port: PortForwardingPort) -> PortForwardingParam {
+  PortForwardingParam::new(name, "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Host("192.168.1.4".to_string()), port)
+    .with_protocol(protocol)
+}
+
+#[test]
+fn test_check() {
+  let validator = Validator::default();
+  let list = vec![
+    rule("web", PortForwardingProtocol::TCP, PortForwardingPort::Simple(8080)),
+    rule("dns", PortForwardingProtocol::UDP, PortForwardingPort::Multiple { remote: (5300, 5310), local: (5300, 5310) }),
+  ];
+  assert_eq!(validator.check(&rule("ok", PortForwardingProtocol::UDP, PortForwardingPort::Simple(8080)), None, &list), vec![]);
+  assert_eq!(validator.check(&list[0], Some(0), &list), vec![]);
+  assert_eq!(validator.check(&rule("dns2", PortForwardingProtocol::Both, PortForwardingPort::Simple(5305)), None, &list), vec![
+    ValidationIssue::Overlap { index: 1, name: "dns".to_string(), remote: (5300, 5310) },
+  ]);
+
+  let bad = PortForwardingParam {
+    remote_addr_min: Some("203.0.113.9".to_string()),
+    remote_addr_max: Some("203.0.113.1".to_string()),
+    ..PortForwardingParam::new("", "IGD.WD1.WCD3.WCPPP1", PortForwardingHost::Mac("aa:bb:cc".to_string()),
+      PortForwardingPort::Multiple { remote: (7500, 7600), local: (7500, 7550) })
+  };
+  assert_eq!(validator.check(&bad, None, &list), vec![
+    ValidationIssue::EmptyName,
+    ValidationIssue::PortRangeMismatch { remote: (7500, 7600), local: (7500, 7550) },
+    ValidationIssue::ReservedPort { port: 7547, usage: "TR-069".to_string() },
+    ValidationIssue::InvalidAddress { field: "remote host range", value: "203.0.113.9-203.0.113.1".to_string() },
+    ValidationIssue::InvalidMac("aa:bb:cc".to_string()),
+  ]);
+  let tr069 = vec![rule("acs", PortForwardingProtocol::TCP, PortForwardingPort::Simple(7547)), list[0].clone(), list[0].clone()];
+  assert_eq!(validator.check(&tr069[0].clone().with_enable(false), Some(0), &tr069), vec![]);
+  assert_eq!(validator.check(&tr069[1].clone().with_enable(false), Some(1), &tr069), vec![]);
+  assert_eq!(validator.check(&rule("acs", PortForwardingProtocol::TCP, PortForwardingPort::Simple(7548)), Some(0), &tr069), vec![]);
+  assert_eq!(validator.check(&tr069[0], None, &tr069), vec![
+    ValidationIssue::ReservedPort { port: 7547, usage: "TR-069".to_string() },
+    ValidationIssue::Overlap { index: 0, name: "acs".to_string(), remote: (7547, 7547) },
+  ]);
+  assert_eq!(validator.check(&rule("http", PortForwardingProtocol::TCP, PortForwardingPort::Transform { remote: 80, local: 8080 }), None, &list), vec![]);
+  let reversed = rule("rev", PortForwardingProtocol::TCP, PortForwardingPort::Multiple { remote: (9000, 8999), local: (1, 2) });
+  assert_eq!(validator.check(&reversed, None, &[]), vec![
+    ValidationIssue::InvalidPortRange { field: "external port", min: 9000, max: 8999 },
+  ]);
+
+  let full = Validator { max_entries: 2, ..Default::default() };
+  assert_eq!(full.check(&rule("ok", PortForwardingProtocol::TCP, PortForwardingPort::Simple(9000)), None, &list), vec![
+    ValidationIssue::TooManyEntries { limit: 2 },
+  ]);
+  assert!(is_valid_mac("AA-bb-cc-dd-ee-01"));
+  assert_eq!(normalize_mac("AA-bb-cc-dd-ee-01").as_deref(), Some("aa:bb:cc:dd:ee:01"));
+  assert_eq!(normalize_mac("aa-bb-cc-dd-ee"), None);
+  assert!(!is_valid_mac("aa:bb:cc:dd:ee:0g"));
+}
+
+#[test]
+fn test_free_external_ports() {
+  let validator = Validator::default();
+  let list = vec![
+    rule("web", PortForwardingProtocol::TCP, PortForwardingPort::Simple(8080)),
+    rule("dns", PortForwardingProtocol::UDP, PortForwardingPort::Multiple { remote: (5300, 5310), local: (5300, 5310) }),
+  ];
+  let tcp = PortForwardingProtocol::TCP;
+  let wan = "IGD.WD1.WCD3.WCPPP1";
+  assert_eq!(validator.free_external_ports(&list, &tcp, wan, 1, (8080, 8090)), Some((8081, 8081)));
+  assert_eq!(validator.free_external_ports(&list, &PortForwardingProtocol::UDP, wan, 1, (8080, 8090)), Some((8080, 8080)));
+  assert_eq!(validator.free_external_ports(&list, &PortForwardingProtocol::Both, wan, 10, (5295, 5400)), Some((5311, 5320)));
+  assert_eq!(validator.free_external_ports(&list, &tcp, "IGD.WD1.WCD1.WCIP1", 1, (8080, 8090)), Some((8080, 8080)));
+  assert_eq!(validator.free_external_ports(&list, &tcp, wan, 2, (7546, 7548)), None);
+  assert_eq!(validator.free_external_ports(&list, &tcp, wan, 1, (58000, 58001)), Some((58001, 58001)));
+  assert_eq!(validator.free_external_ports(&list, &tcp, wan, 0, (8080, 8090)), None);
+}
+}
@@ -0,0 +1,366 @@
+use super::{
+  parse_transfer_meaning, serde_str01_as_bool, serde_str_as_i32, serde_str_as_u32, display_option, target::same_mac,
+  validate::ValidationIssue, Context, OnuError, Result,
+};
+
+/// one radio of `net_wlanm_conf1_t.gch`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct WlanRadio {
+  #[serde(rename = "RadioStatus", with = "serde_str01_as_bool")]
+  pub enable: bool,
+  /// "2.4GHz" or "5GHz"
+  #[serde(rename = "Band")]
+  pub band: String,
+  /// 0 is auto
+  #[serde(rename = "Channel", with = "serde_str_as_u32")]
+  #[tabled(display_with = "display_channel")]
+  pub channel: u32,
+  /// "20MHz", "40MHz", "80MHz" or "Auto"
+  #[serde(rename = "BandWidth")]
+  pub bandwidth: String,
+  /// "100%", "80%", ...
+  #[serde(rename = "TxPower")]
+  pub tx_power: String,
+}
+
+fn display_channel(channel: &u32) -> String {
+  match channel {
+    0 => "auto".to_string(),
+    c => c.to_string(),
+  }
+}
+
+impl WlanRadio {
+  pub fn is_5ghz(&self) -> bool {
+    self.band.starts_with('5')
+  }
+
+  /// channels the radio could be set to, without 0 (auto), 2.4GHz 12-13 are not allowed in every
+  /// country and 5GHz 52-144 need DFS (the radio goes quiet when it meets a radar), so both are left out
+  pub fn channels(&self) -> Vec<u32> {
+    if self.is_5ghz() {
+      [36, 40, 44, 48, 149, 153, 157, 161, 165].to_vec()
+    } else {
+      (1..=11).collect()
+    }
+  }
+
+  /// `key` is the index or the band ("2.4", "5GHz")
+  pub fn matches(&self, index: usize, key: &str) -> bool {
+    let (band, key) = (self.band.to_lowercase(), key.to_lowercase());
+    band == key || band.strip_suffix("ghz") == Some(key.as_str()) || key.parse() == Ok(index)
+  }
+}
+
+/// one SSID of `net_wlanm_essid1_t.gch`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct WlanSsid {
+  #[serde(rename = "Enable", with = "serde_str01_as_bool")]
+  pub enable: bool,
+  /// index of the radio in `Context::wlan_radios`
+  #[serde(rename = "RadioIndex", with = "serde_str_as_u32")]
+  pub radio: u32,
+  #[serde(rename = "ESSID")]
+  pub ssid: String,
+  #[serde(rename = "ESSIDHideEnable", with = "serde_str01_as_bool")]
+  pub hidden: bool,
+  /// "None", "WPA2-PSK", "WPA/WPA2-PSK", "WPA3-SAE", ...
+  #[serde(rename = "BeaconType")]
+  pub security: String,
+  #[serde(rename = "KeyPassphrase")]
+  #[tabled(display_with = "display_secret")]
+  pub passphrase: String,
+}
+
+fn display_secret(s: &str) -> String {
+  if s.is_empty() { String::new() } else { "********".to_string() }
+}
+
+impl WlanSsid {
+  pub fn is_open(&self) -> bool {
+    self.security.eq_ignore_ascii_case("none")
+  }
+
+  /// every problem of the name and passphrase, checked before any POST
+  pub fn check(&self) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if self.ssid.is_empty() || self.ssid.len() > 32 {
+      issues.push(ValidationIssue::InvalidSsid(self.ssid.clone()));
+    }
+    let len = self.passphrase.len();
+    let printable = self.passphrase.chars().all(|c| c.is_ascii() && !c.is_ascii_control());
+    let hex = len == 64 && self.passphrase.chars().all(|c| c.is_ascii_hexdigit());
+    let valid = hex || (8..=63).contains(&len) && printable;
+    if !self.is_open() && !valid {
+      issues.push(ValidationIssue::InvalidPassphrase);
+    }
+    issues
+  }
+}
+
+/// `key` is the index or the name of the SSID
+pub fn find_ssid<'a>(list: &'a [WlanSsid], key: &str) -> Option<(usize, &'a WlanSsid)> {
+  match key.parse::<usize>() {
+    Ok(i) if i < list.len() => Some((i, &list[i])),
+    _ => list.iter().enumerate().find(|(_, s)| s.ssid == key),
+  }
+}
+
+/// one station of `status_wlanm_assoc_t.gch`, `band`, `name` and `ip` are joined by `Context::wlan_clients`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, tabled::Tabled)]
+pub struct WlanClient {
+  #[serde(rename = "MACAddress")]
+  pub mac: String,
+  #[serde(rename = "ESSID")]
+  pub ssid: String,
+  #[serde(skip)]
+  #[tabled(display_with = "display_option")]
+  pub band: Option<String>,
+  /// dBm, e.g. -45
+  #[serde(rename = "RSSI", with = "serde_str_as_i32")]
+  pub rssi: i32,
+  /// Mbps
+  #[serde(rename = "TxRate", with = "serde_str_as_u32")]
+  pub tx_rate: u32,
+  /// Mbps
+  #[serde(rename = "RxRate", with = "serde_str_as_u32")]
+  pub rx_rate: u32,
+  /// seconds
+  #[serde(rename = "AssocTime", with = "serde_str_as_u32")]
+  #[tabled(display_with = "display_seconds")]
+  pub connected_time: u32,
+  /// `LanInfo::name`
+  #[serde(skip)]
+  #[tabled(display_with = "display_option")]
+  pub name: Option<String>,
+  /// `LanInfo::ip`
+  #[serde(skip)]
+  #[tabled(display_with = "display_option")]
+  pub ip: Option<String>,
+}
+
+fn display_seconds(s: &u32) -> String {
+  humantime::format_duration(std::time::Duration::from_secs(*s as u64)).to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct WlanRequest<T> {
+  #[serde(rename = "IF_ACTION")]
+  action: &'static str,
+  #[serde(rename = "IF_INDEX")]
+  index: i32,
+  #[serde(flatten)]
+  params: T,
+}
+
+impl Context {
+  pub fn parse_wlan_radios(resp: &str) -> Result<Vec<WlanRadio>> {
+    let page = "net_wlanm_conf1_t.gch";
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse(page, "IF_INSTNUM"))?;
+    let mut list = Vec::new();
+    for i in 0..count {
+      let field = |name: &str| parse_transfer_meaning(resp, &format!("{}{}", name, i)).unwrap_or_default();
+      list.push(WlanRadio {
+        enable: field("RadioStatus") == "1",
+        band: field("Band"),
+        channel: field("Channel").parse().map_err(|_| OnuError::parse(page, format!("Channel{}", i)))?,
+        bandwidth: field("BandWidth"),
+        tx_power: field("TxPower"),
+      });
+    }
+    Ok(list)
+  }
+
+  pub fn parse_wlan_ssids(resp: &str) -> Result<Vec<WlanSsid>> {
+    let page = "net_wlanm_essid1_t.gch";
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse(page, "IF_INSTNUM"))?;
+    let mut list = Vec::new();
+    for i in 0..count {
+      let field = |name: &str| parse_transfer_meaning(resp, &format!("{}{}", name, i)).unwrap_or_default();
+      list.push(WlanSsid {
+        enable: field("Enable") == "1",
+        radio: field("RadioIndex").parse().map_err(|_| OnuError::parse(page, format!("RadioIndex{}", i)))?,
+        ssid: field("ESSID"),
+        hidden: field("ESSIDHideEnable") == "1",
+        security: field("BeaconType"),
+        passphrase: field("KeyPassphrase"),
+      });
+    }
+    Ok(list)
+  }
+
+  /// the stations only, see `Context::wlan_clients` for the joined list
+  pub fn parse_wlan_clients(resp: &str) -> Result<Vec<WlanClient>> {
+    let page = "status_wlanm_assoc_t.gch";
+    let count = parse_transfer_meaning(resp, "IF_INSTNUM").unwrap_or_default()
+      .parse::<usize>().map_err(|_| OnuError::parse(page, "IF_INSTNUM"))?;
+    let mut list = Vec::new();
+    for i in 0..count {
+      let field = |name: &str| parse_transfer_meaning(resp, &format!("{}{}", name, i)).unwrap_or_default();
+      let number = |name: &str| field(name).parse::<u32>().map_err(|_| OnuError::parse(page, format!("{}{}", name, i)));
+      list.push(WlanClient {
+        mac: field("MACAddress"),
+        ssid: field("ESSID"),
+        band: None,
+        rssi: field("RSSI").parse().map_err(|_| OnuError::parse(page, format!("RSSI{}", i)))?,
+        tx_rate: number("TxRate")?,
+        rx_rate: number("RxRate")?,
+        connected_time: number("AssocTime")?,
+        name: None,
+        ip: None,
+      });
+    }
+    Ok(list)
+  }
+
+  /// the associated stations, with the band of their SSID and the hostname and ip of `Context::lan_info`,
+  /// the page does not tell the radio of a station, so the band is `None` for an SSID on both radios
+  pub async fn wlan_clients(&mut self) -> Result<Vec<WlanClient>> {
+    let (_, resp) = self.fetch("status_wlanm_assoc_t.gch").await?;
+    let mut list = Self::parse_wlan_clients(&resp)?;
+    let radios = self.wlan_radios().await?;
+    let ssids = self.wlan_ssids().await?;
+    let lan = self.lan_info().await?;
+    for client in &mut list {
+      let mut found = ssids.iter().filter(|s| s.ssid == client.ssid).map(|s| s.radio);
+      let radio = found.next().filter(|r| found.all(|other| other == *r));
+      client.band = radio.and_then(|r| radios.get(r as usize)).map(|r| r.band.clone());
+      if let Some(info) = lan.iter().find(|l| same_mac(&l.mac, &client.mac)) {
+        client.name = Some(info.name.clone());
+        client.ip = Some(info.ip.clone());
+      }
+    }
+    Ok(list)
+  }
+
+  pub async fn wlan_radios(&mut self) -> Result<Vec<WlanRadio>> {
+    let (_, resp) = self.fetch("net_wlanm_conf1_t.gch").await?;
+    Self::parse_wlan_radios(&resp)
+  }
+
+  pub async fn wlan_ssids(&mut self) -> Result<Vec<WlanSsid>> {
+    let (_, resp) = self.fetch("net_wlanm_essid1_t.gch").await?;
+    Self::parse_wlan_ssids(&resp)
+  }
+
+  /// overwrite the SSID selected by `key` (see `find_ssid`), clients are disconnected by the router
+  pub async fn wlan_ssid_apply(&mut self, key: &str, ssid: WlanSsid) -> Result<Vec<WlanSsid>> {
+    let issues = ssid.check();
+    if !issues.is_empty() {
+      return Err(OnuError::Validation(issues));
+    }
+    let list = self.wlan_ssids().await?;
+    let (index, _) = find_ssid(&list, key)
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find ssid {}", key)))?;
+    let form = WlanRequest { action: "apply", index: index as i32, params: ssid.clone() };
+    let (err, resp) = self.post("net_wlanm_essid1_t.gch").form(&form).send().await?;
+    err.check()?;
+    let list = Self::parse_wlan_ssids(&resp)?;
+    if list.get(index) != Some(&ssid) {
+      return Err(OnuError::Unconfirmed { action: "apply", rule: format!("ssid {}", ssid.ssid) });
+    }
+    Ok(list)
+  }
+
+  pub async fn wlan_set_ssid(&mut self, key: &str, name: &str) -> Result<Vec<WlanSsid>> {
+    let current = self.wlan_ssid(key).await?;
+    self.wlan_ssid_apply(key, WlanSsid { ssid: name.to_string(), ..current }).await
+  }
+
+  pub async fn wlan_set_passphrase(&mut self, key: &str, passphrase: &str) -> Result<Vec<WlanSsid>> {
+    let current = self.wlan_ssid(key).await?;
+    self.wlan_ssid_apply(key, WlanSsid { passphrase: passphrase.to_string(), ..current }).await
+  }
+
+  async fn wlan_ssid(&mut self, key: &str) -> Result<WlanSsid> {
+    let list = self.wlan_ssids().await?;
+    find_ssid(&list, key).map(|(_, s)| s.clone())
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find ssid {}", key)))
+  }
+
+  /// move the radio selected by `key` (see `WlanRadio::matches`) to `channel`, 0 is auto
+  pub async fn wlan_set_channel(&mut self, key: &str, channel: u32) -> Result<Vec<WlanRadio>> {
+    let list = self.wlan_radios().await?;
+    let (index, radio) = list.iter().enumerate().find(|(i, r)| r.matches(*i, key))
+      .ok_or_else(|| OnuError::InvalidRequest(format!("cannot find radio {}", key)))?;
+    if channel != 0 && !radio.channels().contains(&channel) {
+      return Err(OnuError::Validation(vec![ValidationIssue::InvalidChannel { band: radio.band.clone(), channel }]));
+    }
+    let radio = WlanRadio { channel, ..radio.clone() };
+    let form = WlanRequest { action: "apply", index: index as i32, params: radio.clone() };
+    let (err, resp) = self.post("net_wlanm_conf1_t.gch").form(&form).send().await?;
+    err.check()?;
+    let list = Self::parse_wlan_radios(&resp)?;
+    if list.get(index) != Some(&radio) {
+      return Err(OnuError::Unconfirmed { action: "apply", rule: format!("channel of {}", radio.band) });
+    }
+    Ok(list)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::onu::mock;
+
+#[tokio::test]
+async fn test_wlan() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let radios = ctx.wlan_radios().await?;
+  assert_eq!(radios, mock::MockConfig::default().wlan_radios);
+  let ssids = ctx.wlan_ssids().await?;
+  assert_eq!(ssids, mock::MockConfig::default().wlan_ssids);
+  assert!(radios[0].matches(0, "2.4GHz") && radios[1].matches(1, "5") && radios[1].matches(1, "1"));
+  assert!(!radios[0].matches(0, "5") && !radios[1].matches(1, "2"));
+
+  let list = ctx.wlan_set_passphrase(&ssids[0].ssid, "correct horse battery").await?;
+  assert_eq!(list[0].passphrase, "correct horse battery");
+  let list = ctx.wlan_set_ssid("1", "home-5g").await?;
+  assert_eq!(list[1].ssid, "home-5g");
+  let err = ctx.wlan_set_passphrase("home-5g", "short").await.unwrap_err();
+  assert!(matches!(&err, OnuError::Validation(issues) if issues == &[ValidationIssue::InvalidPassphrase]), "{:?}", err);
+  assert!(ctx.wlan_set_ssid("guest", "x").await.is_err());
+  let list = ctx.wlan_set_ssid("1", "家-5G").await?;
+  assert_eq!(list[1].ssid, "家-5G");
+  let list = ctx.wlan_set_passphrase("家-5G", "correct horse battery").await?;
+  assert_eq!((list[1].ssid.as_str(), list[1].passphrase.as_str()), ("家-5G", "correct horse battery"));
+
+  assert_eq!(ctx.wlan_set_channel("5GHz", 149).await?[1].channel, 149);
+  assert_eq!(ctx.wlan_set_channel("0", 0).await?[0].channel, 0);
+  let err = ctx.wlan_set_channel("2.4", 36).await.unwrap_err();
+  assert!(matches!(err, OnuError::Validation(_)), "{:?}", err);
+  assert!(ctx.wlan_set_channel("5GHz", 52).await.is_err());
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_wlan_clients() -> anyhow::Result<()> {
+  let (_mock, mut ctx) = mock::logged_in(mock::MockConfig::default()).await?;
+  let clients = ctx.wlan_clients().await?;
+  assert_eq!(clients.len(), 2);
+  assert_eq!(clients[0], WlanClient {
+    band: Some("5GHz".to_string()),
+    name: Some("phone".to_string()),
+    ip: Some("192.168.1.5".to_string()),
+    ..mock::MockConfig::default().wlan_clients[0].clone()
+  });
+  assert_eq!(clients[0].rssi, -48);
+  assert_eq!((clients[1].band.as_deref(), clients[1].name.as_deref()), (Some("2.4GHz"), None));
+  assert_eq!(display_seconds(&clients[1].connected_time), "1h 1m 5s");
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_wlan_clients_shared_ssid() -> anyhow::Result<()> {
+  let mut config = mock::MockConfig::default();
+  config.wlan_ssids[1].ssid = config.wlan_ssids[0].ssid.clone();
+  config.wlan_clients[0].ssid = config.wlan_ssids[0].ssid.clone();
+  let (_mock, mut ctx) = mock::logged_in(config).await?;
+  let clients = ctx.wlan_clients().await?;
+  assert_eq!(clients.iter().map(|c| c.band.clone()).collect::<Vec<_>>(), [None, None]);
+  assert_eq!(clients[0].name.as_deref(), Some("phone"));
+  Ok(())
+}
+}